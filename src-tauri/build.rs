@@ -1,3 +1,6 @@
 fn main() {
-    tauri_build::build()
+    // tauri-build only makes sense for the desktop app, the headless core has no window or capabilities
+    if std::env::var_os("CARGO_FEATURE_DESKTOP").is_some() {
+        tauri_build::build()
+    }
 }
@@ -0,0 +1,193 @@
+// Folds the sessions of one app into a single row: a browser and its renderer processes,
+// an Electron app and its helpers. Sessions that share a grouping parameter belong together,
+// the ones without one are grouped by the executable of the app that owns them. The sessions stay reachable through the group.
+use crate::audio_backend::{AudioBackend, DataFlow, SessionDetails};
+use crate::error::AppError;
+
+#[derive(Debug, serde::Serialize, Clone, PartialEq)]
+pub struct AppGroup {
+    pub key: String, // what set_group_volume/set_group_mute take
+    pub display_name: Option<String>, // the first name one of the sessions was given
+    pub exe_name: Option<String>, // the owning app's executable
+    pub data_flow: DataFlow,
+    pub volume: f32, // the loudest session, what the group slider shows
+    pub is_muted: bool, // only when every session is muted
+    pub is_active: bool, // when any session is playing
+    pub sessions: Vec<SessionDetails>,
+}
+
+// playback and recording sessions of the same app are two groups, they show up in different views
+// the backends send it as `SessionDetails::group_key`, the frontend folds its rows by that
+pub fn group_key(session: &SessionDetails) -> String {
+    let data_flow = match session.data_flow {
+        DataFlow::Render => "render",
+        DataFlow::Capture => "capture",
+    };
+    match (&session.grouping_id, app_exe_name(session)) {
+        (Some(grouping_id), _) => format!("{}|grouping|{}", data_flow, grouping_id),
+        (None, Some(exe_name)) => format!("{}|exe|{}", data_flow, exe_name.to_lowercase()),
+        (None, None) => format!("{}|session|{}", data_flow, session.session_uid),
+    }
+}
+
+// the key once the app moved the session to another grouping parameter
+pub fn regrouped_key(session: &SessionDetails, grouping_id: Option<&str>) -> String {
+    group_key(&SessionDetails { grouping_id: grouping_id.map(str::to_string), ..session.clone() })
+}
+
+// a helper's sessions go with its app's, "steamwebhelper.exe" is part of "steam.exe"
+// (a game Steam started isn't, process_info gives it its own identity)
+fn app_exe_name(session: &SessionDetails) -> Option<String> {
+    session.app.as_ref().map(|app| app.exe_name.clone()).or_else(|| session.exe_name.clone())
+}
+
+// groups keep the order their first session was listed in
+pub fn group_sessions(sessions: Vec<SessionDetails>) -> Vec<AppGroup> {
+    let mut groups: Vec<AppGroup> = Vec::new();
+    for session in sessions {
+        let key = group_key(&session);
+        match groups.iter_mut().find(|group| group.key == key) {
+            Some(group) => {
+                group.display_name = group.display_name.take().or_else(|| session.display_name.clone());
+                group.exe_name = group.exe_name.take().or_else(|| app_exe_name(&session));
+                group.volume = group.volume.max(session.session_volume);
+                group.is_muted &= session.is_muted;
+                group.is_active |= session.state.is_active();
+                group.sessions.push(session);
+            }
+            None => groups.push(AppGroup {
+                key,
+                display_name: session.display_name.clone(),
+                exe_name: app_exe_name(&session),
+                data_flow: session.data_flow,
+                volume: session.session_volume,
+                is_muted: session.is_muted,
+                is_active: session.state.is_active(),
+                sessions: vec![session],
+            }),
+        }
+    }
+    groups
+}
+
+pub fn list_groups(backend: &dyn AudioBackend) -> Result<Vec<AppGroup>, AppError> {
+    Ok(group_sessions(backend.list_sessions()?))
+}
+
+fn find_group(backend: &dyn AudioBackend, key: &str) -> Result<AppGroup, AppError> {
+    list_groups(backend)?
+        .into_iter()
+        .find(|group| group.key == key)
+        .ok_or_else(|| AppError::AppGroupNotFound { key: key.to_string() })
+}
+
+// every session of the app gets the same volume, like the Windows mixer does for one grouped row
+// one session failing (e.g. it closed in between) doesn't stop the others, the first error is reported after
+pub fn set_group_volume(backend: &dyn AudioBackend, key: &str, volume: f32) -> Result<(), AppError> {
+    apply_to_group(backend, key, |session| backend.set_volume(session.process_id, &session.session_uid, volume).map(|_| ()))
+}
+
+pub fn set_group_mute(backend: &dyn AudioBackend, key: &str, mute: bool) -> Result<(), AppError> {
+    apply_to_group(backend, key, |session| backend.set_mute(session.process_id, &session.session_uid, mute).map(|_| ()))
+}
+
+fn apply_to_group(backend: &dyn AudioBackend, key: &str, apply: impl Fn(&SessionDetails) -> Result<(), AppError>) -> Result<(), AppError> {
+    let mut first_error = None;
+    for session in find_group(backend, key)?.sessions {
+        if let Err(e) = apply(&session) {
+            eprintln!("[AppGroups] Failed to apply to session {} of {}: {}", session.session_uid, key, e);
+            first_error.get_or_insert(e);
+        }
+    }
+    first_error.map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_backend::mock::{MockBackend, MockCall};
+    use crate::process_info::AppIdentity;
+
+    #[test]
+    fn folds_sessions_by_grouping_parameter_then_executable() {
+        let mut renderer = SessionDetails::test(11, "chrome-2").with_exe("chrome.exe").with_grouping("G1");
+        renderer.session_volume = 0.4;
+        renderer.is_muted = true;
+        let mut helper = SessionDetails::test(12, "discord-2").with_exe("Discord.exe");
+        helper.display_name = Some("Discord".to_string());
+        let mut mic = SessionDetails::test(13, "discord-mic").with_exe("discord.exe");
+        mic.data_flow = DataFlow::Capture;
+        let mut web_helper = SessionDetails::test(41, "steam-web").with_exe("steamwebhelper.exe");
+        web_helper.app = Some(AppIdentity { pid: 40, exe_name: "steam.exe".to_string(), image_path: None });
+        let mut game = SessionDetails::test(42, "game").with_exe("game.exe");
+        game.app = Some(AppIdentity { pid: 42, exe_name: "game.exe".to_string(), image_path: None });
+
+        let groups = group_sessions(vec![
+            SessionDetails::test(10, "chrome-1").with_exe("chrome.exe").with_grouping("G1"),
+            SessionDetails::test(20, "discord-1").with_exe("discord.exe"),
+            renderer,
+            helper,
+            mic,
+            SessionDetails::test(30, "other").with_exe("chrome.exe"), // same exe but no grouping parameter
+            SessionDetails::test(40, "steam").with_exe("steam.exe"),
+            web_helper,
+            game,
+        ]);
+
+        let keys: Vec<&str> = groups.iter().map(|g| g.key.as_str()).collect();
+        assert_eq!(keys, vec!["render|grouping|G1", "render|exe|discord.exe", "capture|exe|discord.exe", "render|exe|chrome.exe", "render|exe|steam.exe", "render|exe|game.exe"]);
+        assert_eq!(groups[0].sessions.len(), 2);
+        assert_eq!(groups[0].volume, 1.0);
+        assert!(!groups[0].is_muted);
+        assert_eq!(groups[1].display_name.as_deref(), Some("Discord"));
+        assert_eq!(groups[1].sessions.len(), 2);
+        assert_eq!(groups[4].sessions.len(), 2);
+    }
+
+    #[test]
+    fn group_commands_reach_every_session_of_the_app() {
+        let backend = MockBackend::with_sessions(vec![
+            SessionDetails::test(10, "chrome-1").with_exe("chrome.exe").with_grouping("G1"),
+            SessionDetails::test(11, "chrome-2").with_exe("chrome.exe").with_grouping("G1"),
+            SessionDetails::test(20, "discord-1").with_exe("discord.exe"),
+        ]);
+
+        set_group_volume(&backend, "render|grouping|G1", 0.3).unwrap();
+        set_group_mute(&backend, "render|grouping|G1", true).unwrap();
+
+        let groups = list_groups(&backend).unwrap();
+        assert_eq!(groups[0].volume, 0.3);
+        assert!(groups[0].is_muted);
+        assert_eq!(groups[1].volume, 1.0);
+        assert!(backend.calls().contains(&MockCall::SetVolume { pid: 11, uid: "chrome-2".to_string(), volume: 0.3 }));
+        assert_eq!(set_group_volume(&backend, "render|exe|missing.exe", 0.5), Err(AppError::AppGroupNotFound { key: "render|exe|missing.exe".to_string() }));
+    }
+
+    #[test]
+    fn a_failing_session_does_not_stop_the_rest_of_the_group() {
+        let backend = MockBackend::with_sessions(vec![
+            SessionDetails::test(10, "chrome-1").with_exe("chrome.exe").with_grouping("G1"),
+            SessionDetails::test(11, "chrome-2").with_exe("chrome.exe").with_grouping("G1"),
+            SessionDetails::test(12, "chrome-3").with_exe("chrome.exe").with_grouping("G1"),
+        ]);
+        backend.fail_session("chrome-2", AppError::backend("refused"));
+
+        assert_eq!(set_group_volume(&backend, "render|grouping|G1", 0.3), Err(AppError::backend("refused")));
+        assert_eq!(set_group_mute(&backend, "render|grouping|G1", true), Err(AppError::backend("refused")));
+
+        assert_eq!(backend.session("chrome-1").unwrap().session_volume, 0.3);
+        assert_eq!(backend.session("chrome-3").unwrap().session_volume, 0.3);
+        assert!(backend.session("chrome-3").unwrap().is_muted);
+        assert_eq!(backend.session("chrome-2").unwrap().session_volume, 1.0);
+    }
+
+    #[test]
+    fn sessions_carry_their_group_key() {
+        let backend = MockBackend::with_sessions(vec![SessionDetails::test(10, "chrome-1").with_exe("chrome.exe")]);
+        assert_eq!(backend.session("chrome-1").unwrap().group_key, "render|exe|chrome.exe");
+
+        backend.change_grouping("chrome-1", Some("G1"));
+        assert_eq!(backend.session("chrome-1").unwrap().group_key, "render|grouping|G1");
+        assert_eq!(regrouped_key(&backend.session("chrome-1").unwrap(), None), "render|exe|chrome.exe");
+    }
+}
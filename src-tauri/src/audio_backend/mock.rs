@@ -0,0 +1,743 @@
+// In-memory AudioBackend for tests and UI development.
+// Sessions are added, removed and changed from code (or loaded from a JSON fixture)
+// and every change is reported to subscribers exactly like the WASAPI listeners do.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+};
+use super::session_id::session_app_key;
+use crate::app_groups;
+use crate::error::AppError;
+use super::{AudioBackend, AudioLevels, ChannelVolumesPayload, DataFlow, DefaultDeviceChangedPayload, DeviceDetails, DeviceLevel, DeviceRole, IconSource, MasterVolume, SessionClosedPayload, SessionCloseReason, SessionDetails, SessionEvent, SessionEventHandler, SessionGroupingPayload, SessionIconPayload, SessionLevel, SessionRenamedPayload, SessionState, SessionStatePayload, Subscription, VolumeChangedPayload};
+
+// every call the app made on the backend, so tests can assert on them
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    ListSessions,
+    ListDevices,
+    SetVolume { pid: u32, uid: String, volume: f32 },
+    SetMute { pid: u32, uid: String, mute: bool },
+    SetChannelVolumes { pid: u32, uid: String, volumes: Vec<f32> },
+    GetMasterVolume { device_id: String },
+    SetMasterVolume { device_id: String, volume: f32 },
+    SetMasterMute { device_id: String, mute: bool },
+}
+
+#[derive(Default)]
+struct MockState {
+    sessions: Vec<SessionDetails>, // a Vec and not a HashMap so listing keeps the insertion order
+    devices: Vec<DeviceDetails>,
+    master_volumes: HashMap<String, MasterVolume>, // devices that were never changed are at full volume
+    channel_volumes: HashMap<String, Vec<f32>>, // keyed by session uid, sessions that were never panned are stereo at full level
+    peaks: HashMap<String, f32>, // keyed by session uid or device id, anything not in here is silent
+    failures: HashMap<String, AppError>, // sessions whose volume and mute calls fail, keyed by uid
+    levels_failure: Option<AppError>,
+    calls: Vec<MockCall>,
+    subscribers: HashMap<u64, SessionEventHandler>,
+    next_subscriber_id: u64,
+}
+
+#[derive(serde::Deserialize)]
+struct MockFixture {
+    #[serde(default)]
+    devices: Vec<DeviceDetails>,
+    sessions: Vec<SessionDetails>,
+}
+
+// cloning gives another handle to the same fake sessions, so a test can keep one
+// and hand the other to the code under test
+#[derive(Clone, Default)]
+pub struct MockBackend {
+    state: Arc<Mutex<MockState>>,
+}
+
+// tests and fixtures leave the group key out, a real backend always sends it
+fn with_group_key(mut session: SessionDetails) -> SessionDetails {
+    if session.group_key.is_empty() {
+        session.group_key = app_groups::group_key(&session);
+    }
+    session
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sessions(sessions: Vec<SessionDetails>) -> Self {
+        let backend = Self::new();
+        backend.lock().sessions = sessions.into_iter().map(with_group_key).collect();
+        backend
+    }
+
+    pub fn with_devices(self, devices: Vec<DeviceDetails>) -> Self {
+        self.lock().devices = devices;
+        self
+    }
+
+    // the fixture holds the devices and sessions in the same shape the frontend receives from list_devices and get_sessions_and_volumes
+    pub fn from_json(fixture: &str) -> Result<Self, String> {
+        let mut fixture: MockFixture = serde_json::from_str(fixture).map_err(|e| format!("Invalid mock session fixture: {}", e))?;
+        // fixtures only give the app key when they want a particular one
+        for session in fixture.sessions.iter_mut().filter(|s| s.app_key.is_none()) {
+            session.app_key = session_app_key(session);
+        }
+        Ok(Self::with_sessions(fixture.sessions).with_devices(fixture.devices))
+    }
+
+    pub fn from_json_file(path: &str) -> Result<Self, String> {
+        let fixture = std::fs::read_to_string(path).map_err(|e| format!("Failed to read mock session fixture {}: {}", path, e))?;
+        Self::from_json(&fixture)
+    }
+
+    // only known devices have a master volume, like a real backend
+    fn master_volume_of(state: &MockState, device_id: &str) -> Result<MasterVolume, AppError> {
+        if !state.devices.iter().any(|d| d.id == device_id) {
+            return Err(AppError::DeviceUnavailable { device_id: device_id.to_string() });
+        }
+        Ok(state.master_volumes.get(device_id).cloned().unwrap_or(MasterVolume { device_id: device_id.to_string(), volume: 1.0, is_muted: false }))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MockState> {
+        // a panicking test thread should not take every other assertion down with it
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // handlers are called without holding the lock so they are free to call back into the backend
+    fn notify(&self, event: SessionEvent) {
+        let handlers: Vec<SessionEventHandler> = self.lock().subscribers.values().cloned().collect();
+        for handler in handlers {
+            handler(event.clone());
+        }
+    }
+
+    pub fn session(&self, uid: &str) -> Option<SessionDetails> {
+        self.lock().sessions.iter().find(|s| s.session_uid == uid).cloned()
+    }
+
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.lock().calls.clone()
+    }
+
+    pub fn clear_calls(&self) {
+        self.lock().calls.clear();
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.lock().subscribers.len()
+    }
+
+    // a new app started playing audio ("audio-session-created")
+    pub fn add_session(&self, details: SessionDetails) {
+        let details = with_group_key(details);
+        self.lock().sessions.push(details.clone());
+        self.notify(SessionEvent::Created(details));
+    }
+
+    // the app closed, its session expired or was disconnected ("audio-session-closed")
+    pub fn remove_session(&self, uid: &str, reason: SessionCloseReason) -> bool {
+        let removed = {
+            let mut state = self.lock();
+            let before = state.sessions.len();
+            state.sessions.retain(|s| s.session_uid != uid);
+            state.channel_volumes.remove(uid);
+            state.sessions.len() != before
+        };
+        if removed {
+            self.notify(SessionEvent::Closed(SessionClosedPayload { session_uid: uid.to_string(), reason }));
+        }
+        removed
+    }
+
+    // volume or mute changed from outside the app, e.g. the Windows mixer ("audio-session-volume-changed")
+    pub fn change_volume(&self, uid: &str, volume: f32, is_muted: bool) -> bool {
+        let changed = match self.lock().sessions.iter_mut().find(|s| s.session_uid == uid) {
+            Some(session) => {
+                session.session_volume = volume;
+                session.is_muted = is_muted;
+                true
+            }
+            None => false,
+        };
+        if changed {
+            self.notify(SessionEvent::VolumeChanged(VolumeChangedPayload { session_uid: uid.to_string(), volume, is_muted }));
+        }
+        changed
+    }
+
+    // the app set (or cleared) the name of its session ("audio-session-renamed")
+    pub fn rename_session(&self, uid: &str, display_name: Option<&str>) -> bool {
+        let display_name = display_name.map(str::to_string);
+        let changed = match self.lock().sessions.iter_mut().find(|s| s.session_uid == uid) {
+            Some(session) => {
+                session.display_name = display_name.clone();
+                true
+            }
+            None => false,
+        };
+        if changed {
+            self.notify(SessionEvent::Renamed(SessionRenamedPayload { session_uid: uid.to_string(), display_name }));
+        }
+        changed
+    }
+
+    // the app pointed its session to another icon ("audio-session-icon-changed")
+    pub fn change_icon(&self, uid: &str, icon_path: Option<&str>) -> bool {
+        let icon_path = icon_path.map(str::to_string);
+        let changed = match self.lock().sessions.iter_mut().find(|s| s.session_uid == uid) {
+            Some(session) => {
+                session.icon_path = icon_path.clone();
+                true
+            }
+            None => false,
+        };
+        if changed {
+            self.notify(SessionEvent::IconChanged(SessionIconPayload { session_uid: uid.to_string(), icon_path }));
+        }
+        changed
+    }
+
+    // the app moved the session to another group ("audio-session-grouping-changed")
+    pub fn change_grouping(&self, uid: &str, grouping_id: Option<&str>) -> bool {
+        let changed = match self.lock().sessions.iter_mut().find(|s| s.session_uid == uid) {
+            Some(session) => {
+                session.grouping_id = grouping_id.map(str::to_string);
+                session.group_key = app_groups::group_key(session);
+                Some(SessionGroupingPayload { session_uid: uid.to_string(), grouping_id: session.grouping_id.clone(), group_key: session.group_key.clone() })
+            }
+            None => None,
+        };
+        match changed {
+            Some(payload) => {
+                self.notify(SessionEvent::GroupingChanged(payload));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // an output device was plugged in or enabled ("device-added"), with the sessions apps already had on it
+    // like on Windows those are reported as created right after the device
+    pub fn add_device(&self, device: DeviceDetails, sessions: Vec<SessionDetails>) {
+        let sessions: Vec<SessionDetails> = sessions.into_iter().map(with_group_key).collect();
+        {
+            let mut state = self.lock();
+            state.devices.push(device.clone());
+            state.sessions.extend(sessions.iter().cloned());
+        }
+        self.notify(SessionEvent::DeviceAdded(device));
+        for session in sessions {
+            self.notify(SessionEvent::Created(session));
+        }
+    }
+
+    // the device was unplugged or disabled ("device-removed"), like on Windows its sessions close first
+    pub fn remove_device(&self, id: &str) -> bool {
+        let (removed, closed_sessions) = {
+            let mut state = self.lock();
+            let before = state.devices.len();
+            state.devices.retain(|d| d.id != id);
+            let removed = state.devices.len() != before;
+            let closed_sessions: Vec<String> = state.sessions.iter().filter(|s| s.device_id == id).map(|s| s.session_uid.clone()).collect();
+            state.sessions.retain(|s| s.device_id != id);
+            for uid in &closed_sessions {
+                state.channel_volumes.remove(uid);
+            }
+            (removed, closed_sessions)
+        };
+        for uid in closed_sessions {
+            self.notify(SessionEvent::Closed(SessionClosedPayload { session_uid: uid, reason: SessionCloseReason::DeviceRemoval }));
+        }
+        if removed {
+            self.notify(SessionEvent::DeviceRemoved(id.to_string()));
+        }
+        removed
+    }
+
+    // the user picked another default output or input in the OS settings ("default-device-changed")
+    // devices of the other direction keep their flags, a microphone is never the default speaker
+    pub fn set_default_device(&self, id: Option<&str>, data_flow: DataFlow, role: DeviceRole) {
+        for device in self.lock().devices.iter_mut().filter(|d| d.data_flow == data_flow) {
+            let is_default = Some(device.id.as_str()) == id;
+            match role {
+                DeviceRole::Console => device.is_default_console = is_default,
+                DeviceRole::Multimedia => device.is_default_multimedia = is_default,
+                DeviceRole::Communications => device.is_default_communications = is_default,
+            }
+        }
+        self.notify(SessionEvent::DefaultDeviceChanged(DefaultDeviceChangedPayload { device_id: id.map(str::to_string), data_flow, role }));
+    }
+
+    // the master volume changed from outside the app, e.g. the volume keys ("master-volume-changed")
+    pub fn change_master_volume(&self, device_id: &str, volume: f32, is_muted: bool) -> bool {
+        let changed = {
+            let mut state = self.lock();
+            if state.devices.iter().any(|d| d.id == device_id) {
+                let master = MasterVolume { device_id: device_id.to_string(), volume, is_muted };
+                state.master_volumes.insert(device_id.to_string(), master.clone());
+                Some(master)
+            } else {
+                None
+            }
+        };
+        match changed {
+            Some(master) => {
+                self.notify(SessionEvent::MasterVolumeChanged(master));
+                true
+            }
+            None => false,
+        }
+    }
+
+    // what the meter of a session or a device shows from now on, 0.0 makes it silent again
+    pub fn set_peak(&self, id: &str, peak: f32) {
+        self.lock().peaks.insert(id.to_string(), peak);
+    }
+
+    // read_levels fails with `error` from now on, like a backend that can't read peaks
+    pub fn fail_levels(&self, error: AppError) {
+        self.lock().levels_failure = Some(error);
+    }
+
+    // set_volume and set_mute on the session fail with `error` from now on, like a session the audio service refuses
+    pub fn fail_session(&self, uid: &str, error: AppError) {
+        self.lock().failures.insert(uid.to_string(), error);
+    }
+
+    // the session started or stopped playing ("session-state-changed")
+    pub fn set_state(&self, uid: &str, state: SessionState) -> bool {
+        let changed = match self.lock().sessions.iter_mut().find(|s| s.session_uid == uid) {
+            Some(session) => {
+                session.state = state;
+                true
+            }
+            None => false,
+        };
+        if changed {
+            self.notify(SessionEvent::StateChanged(SessionStatePayload { session_uid: uid.to_string(), state }));
+        }
+        changed
+    }
+}
+
+impl AudioBackend for MockBackend {
+    fn list_sessions(&self) -> Result<Vec<SessionDetails>, AppError> {
+        let mut state = self.lock();
+        state.calls.push(MockCall::ListSessions);
+        Ok(state.sessions.clone())
+    }
+
+    fn list_devices(&self) -> Result<Vec<DeviceDetails>, AppError> {
+        let mut state = self.lock();
+        state.calls.push(MockCall::ListDevices);
+        Ok(state.devices.clone())
+    }
+
+    // like WASAPI a volume change made by the app itself is reported back through the subscription too
+    fn set_volume(&self, pid: u32, uid: &str, volume: f32) -> Result<VolumeChangedPayload, AppError> {
+        let changed = {
+            let mut state = self.lock();
+            state.calls.push(MockCall::SetVolume { pid, uid: uid.to_string(), volume });
+            if let Some(error) = state.failures.get(uid) {
+                return Err(error.clone());
+            }
+            let session = state.sessions.iter_mut().find(|s| s.process_id == pid && s.session_uid == uid)
+                .ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+            session.session_volume = volume;
+            VolumeChangedPayload { session_uid: uid.to_string(), volume, is_muted: session.is_muted }
+        };
+        self.notify(SessionEvent::VolumeChanged(changed.clone()));
+        Ok(changed)
+    }
+
+    // only a real change of the mute state raises an event, same as the WASAPI backend which skips SetMute when nothing changes
+    fn set_mute(&self, pid: u32, uid: &str, mute: bool) -> Result<VolumeChangedPayload, AppError> {
+        let (changed, applied) = {
+            let mut state = self.lock();
+            state.calls.push(MockCall::SetMute { pid, uid: uid.to_string(), mute });
+            if let Some(error) = state.failures.get(uid) {
+                return Err(error.clone());
+            }
+            let session = state.sessions.iter_mut().find(|s| s.process_id == pid && s.session_uid == uid)
+                .ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+            let changed = session.is_muted != mute;
+            session.is_muted = mute;
+            (changed, VolumeChangedPayload { session_uid: uid.to_string(), volume: session.session_volume, is_muted: mute })
+        };
+        if changed {
+            self.notify(SessionEvent::VolumeChanged(applied.clone()));
+        }
+        Ok(applied)
+    }
+
+    fn get_channel_volumes(&self, pid: u32, uid: &str) -> Result<Vec<f32>, AppError> {
+        let state = self.lock();
+        if !state.sessions.iter().any(|s| s.process_id == pid && s.session_uid == uid) {
+            return Err(AppError::SessionNotFound { uid: uid.to_string() });
+        }
+        Ok(state.channel_volumes.get(uid).cloned().unwrap_or_else(|| vec![1.0, 1.0]))
+    }
+
+    fn set_channel_volumes(&self, pid: u32, uid: &str, volumes: &[f32]) -> Result<(), AppError> {
+        let changed = {
+            let mut state = self.lock();
+            state.calls.push(MockCall::SetChannelVolumes { pid, uid: uid.to_string(), volumes: volumes.to_vec() });
+            if !state.sessions.iter().any(|s| s.process_id == pid && s.session_uid == uid) {
+                return Err(AppError::SessionNotFound { uid: uid.to_string() });
+            }
+            let channel_count = state.channel_volumes.get(uid).map_or(2, Vec::len);
+            if volumes.len() != channel_count {
+                return Err(AppError::InvalidArgument(format!("Session {} has {} channels, got {} volumes", uid, channel_count, volumes.len())));
+            }
+            state.channel_volumes.insert(uid.to_string(), volumes.to_vec());
+            ChannelVolumesPayload { session_uid: uid.to_string(), volumes: volumes.to_vec() }
+        };
+        self.notify(SessionEvent::ChannelsChanged(changed));
+        Ok(())
+    }
+
+    fn get_master_volume(&self, device_id: &str) -> Result<MasterVolume, AppError> {
+        let mut state = self.lock();
+        state.calls.push(MockCall::GetMasterVolume { device_id: device_id.to_string() });
+        Self::master_volume_of(&state, device_id)
+    }
+
+    // the endpoint volume callback fires for our own changes too, so this is reported back like set_volume
+    fn set_master_volume(&self, device_id: &str, volume: f32) -> Result<(), AppError> {
+        let master = {
+            let mut state = self.lock();
+            state.calls.push(MockCall::SetMasterVolume { device_id: device_id.to_string(), volume });
+            let master = MasterVolume { volume, ..Self::master_volume_of(&state, device_id)? };
+            state.master_volumes.insert(device_id.to_string(), master.clone());
+            master
+        };
+        self.notify(SessionEvent::MasterVolumeChanged(master));
+        Ok(())
+    }
+
+    fn set_master_mute(&self, device_id: &str, mute: bool) -> Result<(), AppError> {
+        let changed = {
+            let mut state = self.lock();
+            state.calls.push(MockCall::SetMasterMute { device_id: device_id.to_string(), mute });
+            let master = Self::master_volume_of(&state, device_id)?;
+            if master.is_muted != mute {
+                let master = MasterVolume { is_muted: mute, ..master };
+                state.master_volumes.insert(device_id.to_string(), master.clone());
+                Some(master)
+            } else {
+                None
+            }
+        };
+        if let Some(master) = changed {
+            self.notify(SessionEvent::MasterVolumeChanged(master));
+        }
+        Ok(())
+    }
+
+    // the fixtures only have exe names, they stand in for the full path
+    fn icon_source(&self, pid: u32, uid: &str) -> Result<IconSource, AppError> {
+        let state = self.lock();
+        let session = state
+            .sessions
+            .iter()
+            .find(|s| s.process_id == pid && s.session_uid == uid)
+            .ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+        Ok(IconSource { exe_path: session.exe_name.clone(), icon_path: session.icon_path.clone() })
+    }
+
+    // not recorded in calls, the level meter reads many times a second
+    fn read_levels(&self) -> Result<AudioLevels, AppError> {
+        let state = self.lock();
+        if let Some(error) = &state.levels_failure {
+            return Err(error.clone());
+        }
+        let peak_of = |id: &str| state.peaks.get(id).copied().unwrap_or(0.0);
+        Ok(AudioLevels {
+            sessions: state.sessions.iter().map(|s| SessionLevel { session_uid: s.session_uid.clone(), peak: peak_of(&s.session_uid) }).collect(),
+            devices: state.devices.iter().map(|d| DeviceLevel { device_id: d.id.clone(), peak: peak_of(&d.id) }).collect(),
+        })
+    }
+
+    fn subscribe(&self, on_event: SessionEventHandler) -> Result<Subscription, AppError> {
+        let id = {
+            let mut state = self.lock();
+            let id = state.next_subscriber_id;
+            state.next_subscriber_id += 1;
+            state.subscribers.insert(id, on_event);
+            id
+        };
+
+        let state = Arc::downgrade(&self.state);
+        Ok(Subscription::new(move || {
+            if let Some(state) = state.upgrade() {
+                state.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).subscribers.remove(&id);
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_backend::{system_sounds_uid, DeviceState};
+    use std::sync::mpsc;
+
+    const FIXTURE: &str = include_str!("../../fixtures/mock_sessions.json");
+
+    fn subscribe(backend: &MockBackend) -> (Subscription, mpsc::Receiver<SessionEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        let subscription = backend
+            .subscribe(Arc::new(move |event| {
+                let _ = sender.send(event);
+            }))
+            .unwrap();
+        (subscription, receiver)
+    }
+
+    #[test]
+    fn loads_sessions_from_the_json_fixture() {
+        let backend = MockBackend::from_json(FIXTURE).unwrap();
+        let sessions = backend.list_sessions().unwrap();
+
+        assert_eq!(sessions.len(), 5);
+        assert_eq!(sessions[0].process_id, 4120);
+        assert_eq!(sessions[0].display_name.as_deref(), Some("Spotify"));
+        assert_eq!(sessions[1].icon_path.as_deref(), Some("C:\\Users\\mock\\AppData\\Local\\Discord\\app.ico"));
+        assert_eq!(sessions[2].display_name, None);
+        assert_eq!(sessions[2].exe_name.as_deref(), Some("chrome.exe"));
+        assert_eq!(sessions[0].data_flow, DataFlow::Render);
+        assert!(sessions[2].is_muted);
+        assert_eq!(sessions[2].device_id, "mock-headphones");
+        assert_eq!(sessions[3].data_flow, DataFlow::Capture);
+        assert_eq!(sessions[0].app_key.as_deref(), Some("app:spotify.exe"));
+        assert!(sessions[4].is_system_sounds);
+        assert_eq!(sessions[4].app_key.as_deref(), Some("system-sounds"));
+        assert_eq!(sessions[4].session_uid, system_sounds_uid("mock-speakers"));
+
+        let devices = backend.list_devices().unwrap();
+        assert_eq!(devices.len(), 3);
+        assert_eq!(devices[2].data_flow, DataFlow::Capture);
+        assert_eq!(devices[0].state, DeviceState::Active);
+        assert!(devices[0].is_default_console);
+        assert!(!devices[1].is_default_multimedia);
+        assert_eq!(backend.calls(), vec![MockCall::ListSessions, MockCall::ListDevices]);
+
+        // controlled like any other session, with pid 0
+        backend.set_volume(0, &sessions[4].session_uid, 0.2).unwrap();
+        assert_eq!(backend.list_sessions().unwrap()[4].session_volume, 0.2);
+    }
+
+    #[test]
+    fn rejects_a_malformed_fixture() {
+        assert!(MockBackend::from_json("{ \"pid\": 1 }").is_err());
+    }
+
+    #[test]
+    fn set_volume_updates_the_matching_session_and_reports_it() {
+        let backend = MockBackend::with_sessions(vec![SessionDetails::test(10, "a").with_exe("A"), SessionDetails::test(11, "b").with_exe("B")]);
+        let (_subscription, events) = subscribe(&backend);
+
+        let applied = backend.set_volume(10, "a", 0.25).unwrap();
+        assert_eq!(applied, VolumeChangedPayload { session_uid: "a".to_string(), volume: 0.25, is_muted: false });
+
+        assert_eq!(backend.session("a").unwrap().session_volume, 0.25);
+        assert_eq!(backend.session("b").unwrap().session_volume, 1.0);
+        assert_eq!(backend.calls(), vec![MockCall::SetVolume { pid: 10, uid: "a".to_string(), volume: 0.25 }]);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::VolumeChanged(VolumeChangedPayload { session_uid: "a".to_string(), volume: 0.25, is_muted: false })
+        );
+    }
+
+    #[test]
+    fn set_volume_needs_both_pid_and_uid_to_match() {
+        let backend = MockBackend::with_sessions(vec![SessionDetails::test(10, "a").with_exe("A")]);
+        let (_subscription, events) = subscribe(&backend);
+
+        assert_eq!(backend.set_volume(99, "a", 0.1), Err(AppError::SessionNotFound { uid: "a".to_string() }));
+        assert_eq!(backend.set_mute(10, "b", true), Err(AppError::SessionNotFound { uid: "b".to_string() }));
+
+        assert_eq!(backend.session("a").unwrap().session_volume, 1.0);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn set_mute_only_reports_real_changes() {
+        let backend = MockBackend::with_sessions(vec![SessionDetails::test(10, "a").with_exe("A")]);
+        let (_subscription, events) = subscribe(&backend);
+
+        // nothing changes but the caller still gets the state back
+        assert!(!backend.set_mute(10, "a", false).unwrap().is_muted);
+        assert!(events.try_recv().is_err());
+
+        backend.set_mute(10, "a", true).unwrap();
+        assert!(backend.session("a").unwrap().is_muted);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::VolumeChanged(VolumeChangedPayload { session_uid: "a".to_string(), volume: 1.0, is_muted: true })
+        );
+        assert_eq!(backend.calls().len(), 2);
+    }
+
+    #[test]
+    fn scripted_changes_raise_the_same_events_as_the_listeners() {
+        let backend = MockBackend::new();
+        let (_subscription, events) = subscribe(&backend);
+
+        backend.add_session(SessionDetails::test(10, "a").with_exe("A"));
+        backend.set_state("a", SessionState::Inactive);
+        backend.change_volume("a", 0.5, true);
+        backend.remove_session("a", SessionCloseReason::ExclusiveModeOverride);
+
+        assert_eq!(events.try_recv().unwrap(), SessionEvent::Created(with_group_key(SessionDetails::test(10, "a").with_exe("A"))));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::StateChanged(SessionStatePayload { session_uid: "a".to_string(), state: SessionState::Inactive })
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::VolumeChanged(VolumeChangedPayload { session_uid: "a".to_string(), volume: 0.5, is_muted: true })
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::Closed(SessionClosedPayload { session_uid: "a".to_string(), reason: SessionCloseReason::ExclusiveModeOverride })
+        );
+        assert!(backend.list_sessions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn renames_and_icon_changes_update_the_session() {
+        let backend = MockBackend::with_sessions(vec![SessionDetails::test(10, "a").with_exe("a.exe")]);
+        let (_subscription, events) = subscribe(&backend);
+
+        backend.rename_session("a", Some("Game"));
+        backend.change_icon("a", Some("C:\\game\\game.ico"));
+        backend.rename_session("a", None);
+
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::Renamed(SessionRenamedPayload { session_uid: "a".to_string(), display_name: Some("Game".to_string()) })
+        );
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::IconChanged(SessionIconPayload { session_uid: "a".to_string(), icon_path: Some("C:\\game\\game.ico".to_string()) })
+        );
+        assert_eq!(events.try_recv().unwrap(), SessionEvent::Renamed(SessionRenamedPayload { session_uid: "a".to_string(), display_name: None }));
+        let renamed = backend.session("a").unwrap();
+        assert_eq!(renamed.display_name, None);
+        assert_eq!(renamed.icon_path.as_deref(), Some("C:\\game\\game.ico"));
+        assert!(!backend.rename_session("missing", Some("x")));
+    }
+
+    #[test]
+    fn removing_a_device_closes_its_sessions_first() {
+        let backend = MockBackend::from_json(FIXTURE).unwrap();
+        let (_subscription, events) = subscribe(&backend);
+
+        assert!(backend.remove_device("mock-headphones"));
+
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::Closed(SessionClosedPayload { session_uid: "mock|chrome.exe|1".to_string(), reason: SessionCloseReason::DeviceRemoval })
+        );
+        assert_eq!(events.try_recv().unwrap(), SessionEvent::DeviceRemoved("mock-headphones".to_string()));
+        assert_eq!(backend.list_sessions().unwrap().len(), 4);
+        assert_eq!(backend.list_devices().unwrap().len(), 2);
+        assert!(!backend.remove_device("mock-headphones"));
+    }
+
+    #[test]
+    fn default_device_changes_move_the_role_flag() {
+        let backend = MockBackend::from_json(FIXTURE).unwrap();
+        let (_subscription, events) = subscribe(&backend);
+
+        backend.set_default_device(Some("mock-headphones"), DataFlow::Render, DeviceRole::Console);
+
+        let devices = backend.list_devices().unwrap();
+        assert!(!devices[0].is_default_console);
+        assert!(devices[1].is_default_console);
+        assert!(devices[0].is_default_multimedia); // other roles are left alone
+        assert!(devices[2].is_default_console); // and so is the microphone
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::DefaultDeviceChanged(DefaultDeviceChangedPayload { device_id: Some("mock-headphones".to_string()), data_flow: DataFlow::Render, role: DeviceRole::Console })
+        );
+    }
+
+    #[test]
+    fn channel_volumes_are_checked_and_reported() {
+        let backend = MockBackend::with_sessions(vec![SessionDetails::test(10, "a").with_exe("A")]);
+        let (_subscription, events) = subscribe(&backend);
+
+        assert_eq!(backend.get_channel_volumes(10, "a").unwrap(), vec![1.0, 1.0]);
+        assert!(backend.set_channel_volumes(10, "a", &[0.5]).is_err());
+        backend.set_channel_volumes(10, "a", &[0.5, 1.0]).unwrap();
+
+        assert_eq!(backend.get_channel_volumes(10, "a").unwrap(), vec![0.5, 1.0]);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            SessionEvent::ChannelsChanged(ChannelVolumesPayload { session_uid: "a".to_string(), volumes: vec![0.5, 1.0] })
+        );
+        assert_eq!(backend.get_channel_volumes(99, "a"), Err(AppError::SessionNotFound { uid: "a".to_string() }));
+    }
+
+    #[test]
+    fn channel_volumes_of_a_missing_session_are_not_found() {
+        let backend = MockBackend::with_sessions(vec![SessionDetails::test(10, "a").with_exe("A")]);
+        let (_subscription, events) = subscribe(&backend);
+
+        // another pid, then a uid nobody has, set_balance goes through set_channel_volumes
+        assert_eq!(backend.set_channel_volumes(99, "a", &[0.5, 1.0]), Err(AppError::SessionNotFound { uid: "a".to_string() }));
+        assert_eq!(backend.set_balance(10, "gone", 0.5), Err(AppError::SessionNotFound { uid: "gone".to_string() }));
+        assert_eq!(backend.get_channel_volumes(10, "a").unwrap(), vec![1.0, 1.0]);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn master_volume_is_kept_per_device_and_reported() {
+        let backend = MockBackend::from_json(FIXTURE).unwrap();
+        let (_subscription, events) = subscribe(&backend);
+
+        backend.set_master_volume("mock-speakers", 0.4).unwrap();
+        backend.set_master_mute("mock-speakers", false).unwrap(); // not a change, no event
+        backend.change_master_volume("mock-headphones", 0.8, true);
+        backend.set_master_mute("mock-microphone", true).unwrap(); // the mic is muted the same way
+
+        let speakers = MasterVolume { device_id: "mock-speakers".to_string(), volume: 0.4, is_muted: false };
+        let headphones = MasterVolume { device_id: "mock-headphones".to_string(), volume: 0.8, is_muted: true };
+        assert_eq!(backend.get_master_volume("mock-speakers").unwrap(), speakers);
+        assert_eq!(backend.get_master_volume("mock-headphones").unwrap(), headphones);
+        assert_eq!(events.try_recv().unwrap(), SessionEvent::MasterVolumeChanged(speakers));
+        assert_eq!(events.try_recv().unwrap(), SessionEvent::MasterVolumeChanged(headphones));
+        assert!(matches!(events.try_recv().unwrap(), SessionEvent::MasterVolumeChanged(m) if m.device_id == "mock-microphone" && m.is_muted));
+        assert!(events.try_recv().is_err());
+
+        assert!(matches!(backend.get_master_volume("missing"), Err(AppError::DeviceUnavailable { .. })));
+        assert!(backend.set_master_mute("missing", true).is_err());
+        assert_eq!(backend.set_master_volume("missing", 0.5), Err(AppError::DeviceUnavailable { device_id: "missing".to_string() }));
+    }
+
+    #[test]
+    fn changes_to_unknown_sessions_are_ignored() {
+        let backend = MockBackend::new();
+        let (_subscription, events) = subscribe(&backend);
+
+        assert!(!backend.remove_session("missing", SessionCloseReason::Expired));
+        assert!(!backend.change_volume("missing", 0.5, false));
+        assert!(!backend.set_state("missing", SessionState::Active));
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn dropping_the_subscription_stops_events() {
+        let backend = MockBackend::new();
+        let (subscription, events) = subscribe(&backend);
+        assert_eq!(backend.subscriber_count(), 1);
+
+        drop(subscription);
+        backend.add_session(SessionDetails::test(10, "a").with_exe("A"));
+
+        assert_eq!(backend.subscriber_count(), 0);
+        assert!(events.try_recv().is_err());
+    }
+}
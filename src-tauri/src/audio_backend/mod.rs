@@ -0,0 +1,472 @@
+// Platform-neutral interface to the operating system's audio sessions.
+// The tauri commands and the audio monitor only talk to the `AudioBackend` trait,
+// the actual OS calls (WASAPI/COM on Windows, PulseAudio/PipeWire on Linux) live in the platform modules below.
+use std::sync::Arc;
+use crate::error::AppError;
+use crate::process_info::AppIdentity;
+
+pub mod mock;
+pub mod session_id;
+#[cfg(windows)]
+mod wasapi;
+#[cfg(target_os = "linux")]
+mod pulse;
+#[cfg(not(any(windows, target_os = "linux")))]
+mod unsupported;
+
+// which way the audio goes: render is playback (speakers, headphones), capture is recording (microphones)
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DataFlow {
+    #[default]
+    Render,
+    Capture,
+}
+
+// the system sounds session has no process to name it or tell it apart, so it gets a name and a uid
+// that stay the same across restarts. There is one per device
+pub const SYSTEM_SOUNDS_NAME: &str = "System Sounds";
+
+pub fn system_sounds_uid(device_id: &str) -> String {
+    format!("system-sounds|{}", device_id)
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)] // Serialize to send it to the frontend, Deserialize for the mock fixtures
+// Struct to hold session details to send to the frontend
+pub struct SessionDetails {
+    #[serde(rename = "pid")] // <-- RENAME FOR SERIALIZATION
+    pub process_id: u32,
+
+    #[serde(rename = "uid")] // <-- RENAME FOR SERIALIZATION
+    pub session_uid: String,
+
+    // the name the app gave its session, most apps never set one
+    #[serde(default)]
+    pub display_name: Option<String>,
+
+    // the executable of the owning process ("chrome.exe"), what the UI falls back to
+    #[serde(default)]
+    pub exe_name: Option<String>,
+
+    // the icon the app gave its session, an icon file or a resource in a dll/exe
+    #[serde(default)]
+    pub icon_path: Option<String>,
+
+    // sessions with the same grouping parameter belong to one app (a browser and its renderer processes)
+    #[serde(default)]
+    pub grouping_id: Option<String>,
+
+    // the app group the session is shown in, built from the fields above by `app_groups::group_key`
+    // the frontend folds its rows by it and set_group_volume/set_group_mute take it
+    #[serde(default)]
+    pub group_key: String,
+
+    // the application the owning process belongs to, for a helper process that's the app that started it
+    #[serde(default)]
+    pub app: Option<AppIdentity>,
+
+    // stays the same when the app restarts, unlike the uid, what per-app settings are stored under (see `session_id`)
+    #[serde(default)]
+    pub app_key: Option<String>,
+
+    // the Windows system sounds session: pid 0, no process, `system_sounds_uid` as its uid
+    #[serde(default)]
+    pub is_system_sounds: bool,
+
+    #[serde(rename = "volume")] // <-- RENAME FOR SERIALIZATION
+    pub session_volume: f32,
+
+    pub is_muted: bool,
+    pub state: SessionState,
+
+    // the device the session plays to (or records from), same id as `DeviceDetails::id`
+    pub device_id: String,
+
+    // render for apps playing sound, capture for apps using a microphone
+    #[serde(default)] // fixtures written before capture sessions existed only have playback
+    pub data_flow: DataFlow,
+}
+
+// the session every module's tests start from, an active playback session of "test.exe" on the mock speakers
+// tests set any other field on the value they get back
+#[cfg(test)]
+impl SessionDetails {
+    pub(crate) fn test(pid: u32, uid: &str) -> Self {
+        SessionDetails {
+            process_id: pid,
+            session_uid: uid.to_string(),
+            display_name: None,
+            exe_name: Some("test.exe".to_string()),
+            icon_path: None,
+            grouping_id: None,
+            group_key: String::new(),
+            app: None,
+            app_key: None,
+            is_system_sounds: false,
+            session_volume: 1.0,
+            is_muted: false,
+            state: SessionState::Active,
+            device_id: "mock-speakers".to_string(),
+            data_flow: DataFlow::Render,
+        }
+    }
+
+    pub(crate) fn with_exe(mut self, exe_name: &str) -> Self {
+        self.exe_name = Some(exe_name.to_string());
+        self
+    }
+
+    pub(crate) fn with_grouping(mut self, grouping_id: &str) -> Self {
+        self.grouping_id = Some(grouping_id.to_string());
+        self
+    }
+}
+
+// same states as WASAPI's AudioSessionState, on Linux a corked stream is inactive
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    Active, // playing (or recording)
+    Inactive, // open but silent, e.g. a paused player
+    Expired, // the app released it, it gets closed right after
+}
+
+impl SessionState {
+    pub fn is_active(self) -> bool {
+        self == SessionState::Active
+    }
+}
+
+// why a session went away, the first six are WASAPI's AudioSessionDisconnectReason
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionCloseReason {
+    DeviceRemoval, // the device was unplugged or disabled
+    ServerShutdown, // the audio service stopped
+    FormatChanged, // the device's format was changed in the sound settings
+    SessionLogoff, // the user logged off
+    SessionDisconnected, // the remote desktop session it played to was disconnected
+    ExclusiveModeOverride, // another app took the device in exclusive mode
+    Expired, // the app released the session or exited, the usual case
+    Moved, // the stream went to another device (PulseAudio), it is created again there right after
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceState {
+    Active,
+    Disabled,
+    NotPresent,
+    Unplugged,
+}
+
+// an output (render) or input (capture) device as sent by list_devices
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct DeviceDetails {
+    pub id: String,
+    pub name: String, // the friendly name, e.g. "Speakers (Realtek High Definition Audio)"
+    pub state: DeviceState,
+    #[serde(default)]
+    pub data_flow: DataFlow,
+    // Windows keeps a default device per role and direction, on Linux the one default sink (or source) has all three
+    pub is_default_console: bool,
+    pub is_default_multimedia: bool,
+    pub is_default_communications: bool,
+}
+
+// also what set_volume and set_mute return, the state read back from the session after the change
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct VolumeChangedPayload {
+    // Use serde to rename the field to match the frontend's expectation
+    #[serde(rename = "uid")]
+    pub session_uid: String,
+    #[serde(rename = "newVolume")]
+    pub volume: f32,
+    #[serde(rename = "isMuted")]
+    pub is_muted: bool,
+}
+
+#[derive(Debug, serde::Serialize, Clone, PartialEq)]
+pub struct SessionStatePayload {
+    #[serde(rename = "uid")]
+    pub session_uid: String,
+    pub state: SessionState,
+}
+
+#[derive(Debug, serde::Serialize, Clone, PartialEq)]
+pub struct SessionClosedPayload {
+    #[serde(rename = "uid")]
+    pub session_uid: String,
+    pub reason: SessionCloseReason,
+}
+
+// the app changed the name of its session, None when it cleared it
+#[derive(Debug, serde::Serialize, Clone, PartialEq)]
+pub struct SessionRenamedPayload {
+    #[serde(rename = "uid")]
+    pub session_uid: String,
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, Clone, PartialEq)]
+pub struct SessionIconPayload {
+    #[serde(rename = "uid")]
+    pub session_uid: String,
+    pub icon_path: Option<String>,
+}
+
+// the session moved to another app group, None when it has no grouping parameter anymore
+#[derive(Debug, serde::Serialize, Clone, PartialEq)]
+pub struct SessionGroupingPayload {
+    #[serde(rename = "uid")]
+    pub session_uid: String,
+    pub grouping_id: Option<String>,
+    pub group_key: String, // the session's new app group
+}
+
+// the level of every channel of a session, in the device's channel order (front left, front right, ...)
+// these multiply the session volume on Windows, on Linux they are the stream's own channel levels
+#[derive(Debug, serde::Serialize, Clone, PartialEq)]
+pub struct ChannelVolumesPayload {
+    #[serde(rename = "uid")]
+    pub session_uid: String,
+    pub volumes: Vec<f32>,
+}
+
+// the master (endpoint) volume of a device, the one the Windows volume flyout shows for outputs
+// and the microphone level for inputs. returned by get_master_volume and sent with "master-volume-changed"
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct MasterVolume {
+    pub device_id: String,
+    pub volume: f32,
+    pub is_muted: bool,
+}
+
+// one peak sample, 0.0 is silence and 1.0 full scale
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct SessionLevel {
+    #[serde(rename = "uid")]
+    pub session_uid: String,
+    pub peak: f32,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq)]
+pub struct DeviceLevel {
+    pub device_id: String,
+    pub peak: f32,
+}
+
+// the peaks of the sessions and devices read in one go, the level meter sends them batched as "audio-levels"
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Default)]
+pub struct AudioLevels {
+    pub sessions: Vec<SessionLevel>,
+    pub devices: Vec<DeviceLevel>,
+}
+
+// where the icon of a session can come from: the icon the app set, else the executable of its process
+// serialized only for a desktop app attached to the daemon, it loads the icon itself
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Default)]
+pub struct IconSource {
+    pub exe_path: Option<String>, // the full path, the icon cache is keyed by it
+    pub icon_path: Option<String>,
+}
+
+// the three roles Windows keeps a default device for
+#[derive(Debug, serde::Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceRole {
+    Console,
+    Multimedia,
+    Communications,
+}
+
+#[derive(Debug, serde::Serialize, Clone, PartialEq)]
+pub struct DefaultDeviceChangedPayload {
+    pub device_id: Option<String>, // None when the last device of that direction went away
+    pub data_flow: DataFlow,
+    pub role: DeviceRole,
+}
+
+// Everything a backend reports about sessions and output devices after a subscription is made.
+// The audio monitor turns each variant into the matching frontend event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    Created(SessionDetails), // "audio-session-created"
+    Closed(SessionClosedPayload), // "audio-session-closed"
+    VolumeChanged(VolumeChangedPayload), // "audio-session-volume-changed"
+    StateChanged(SessionStatePayload), // "session-state-changed"
+    ChannelsChanged(ChannelVolumesPayload), // "audio-session-channels-changed"
+    Renamed(SessionRenamedPayload), // "audio-session-renamed"
+    IconChanged(SessionIconPayload), // "audio-session-icon-changed"
+    GroupingChanged(SessionGroupingPayload), // "audio-session-grouping-changed"
+    DeviceAdded(DeviceDetails), // "device-added", an output or input device became active (plugged in, enabled...)
+    DeviceRemoved(String), // "device-removed", carries the device id. its sessions are closed before this
+    DefaultDeviceChanged(DefaultDeviceChangedPayload), // "default-device-changed"
+    MasterVolumeChanged(MasterVolume), // "master-volume-changed"
+}
+
+// callback given to a backend by `subscribe`, backends may call it from any thread
+pub type SessionEventHandler = Arc<dyn Fn(SessionEvent) + Send + Sync>;
+
+// Keeps a backend subscription alive, dropping it stops the events and releases
+// whatever the backend registered with the OS (listeners, threads...).
+pub struct Subscription {
+    cancel: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Subscription {
+    pub fn new(cancel: impl FnOnce() + Send + 'static) -> Self {
+        Self { cancel: Some(Box::new(cancel)) }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            cancel();
+        }
+    }
+}
+
+pub trait AudioBackend: Send + Sync {
+    // gets already running audio sessions, playback sessions on every output device and recording sessions on every input device
+    fn list_sessions(&self) -> Result<Vec<SessionDetails>, AppError>;
+
+    // gets the active output and input devices
+    fn list_devices(&self) -> Result<Vec<DeviceDetails>, AppError>;
+
+    // both fail with SessionNotFound when the pid no longer owns the uid, and otherwise return the volume and mute state the session has now
+    fn set_volume(&self, pid: u32, uid: &str, volume: f32) -> Result<VolumeChangedPayload, AppError>;
+
+    fn set_mute(&self, pid: u32, uid: &str, mute: bool) -> Result<VolumeChangedPayload, AppError>;
+
+    // every session of the process gets the volume, for a uid that went stale: an app that recreated its session got a new uid
+    fn set_process_volume(&self, pid: u32, volume: f32) -> Result<Vec<VolumeChangedPayload>, AppError> {
+        process_sessions(self, pid)?.iter().map(|session| self.set_volume(pid, &session.session_uid, volume)).collect()
+    }
+
+    fn set_process_mute(&self, pid: u32, mute: bool) -> Result<Vec<VolumeChangedPayload>, AppError> {
+        process_sessions(self, pid)?.iter().map(|session| self.set_mute(pid, &session.session_uid, mute)).collect()
+    }
+
+    fn get_channel_volumes(&self, pid: u32, uid: &str) -> Result<Vec<f32>, AppError>;
+
+    // `volumes` needs one level per channel, as many as get_channel_volumes returns
+    fn set_channel_volumes(&self, pid: u32, uid: &str, volumes: &[f32]) -> Result<(), AppError>;
+
+    // pans a session left or right, `balance` goes from -1.0 (full left) over 0.0 (centered) to 1.0 (full right)
+    fn set_balance(&self, pid: u32, uid: &str, balance: f32) -> Result<(), AppError> {
+        let current = self.get_channel_volumes(pid, uid)?;
+        // the loudest channel keeps its level so panning doesn't change how loud the app is
+        let level = current.iter().copied().fold(0.0, f32::max);
+        let volumes: Vec<f32> = balance_volumes(balance, current.len()).into_iter().map(|volume| volume * level).collect();
+        self.set_channel_volumes(pid, uid, &volumes)
+    }
+
+    // master volume of a device, `device_id` is a `DeviceDetails::id`. for a microphone this is the input level,
+    // and set_master_mute on it mutes the mic for every app at once
+    fn get_master_volume(&self, device_id: &str) -> Result<MasterVolume, AppError>;
+
+    fn set_master_volume(&self, device_id: &str, volume: f32) -> Result<(), AppError>;
+
+    fn set_master_mute(&self, device_id: &str, mute: bool) -> Result<(), AppError>;
+
+    // what the icon subsystem needs to find the session's icon
+    fn icon_source(&self, pid: u32, uid: &str) -> Result<IconSource, AppError>;
+
+    // the current peak of every session and device, the level meter calls this many times a second
+    // AppError::Unsupported when the backend can't read peaks at all, the UI then hides the meters
+    fn read_levels(&self) -> Result<AudioLevels, AppError>;
+
+    // starts reporting created/closed/volume/state changes, device changes and master volume changes to `on_event` until the returned Subscription is dropped
+    fn subscribe(&self, on_event: SessionEventHandler) -> Result<Subscription, AppError>;
+
+    // stops whatever the backend runs in the background (the WASAPI COM worker) and waits for it, called once on exit
+    // after the monitor and the level meter are stopped. blocks, and the backend should not be used after it
+    fn shutdown(&self) {}
+}
+
+fn process_sessions<B: AudioBackend + ?Sized>(backend: &B, pid: u32) -> Result<Vec<SessionDetails>, AppError> {
+    let sessions: Vec<SessionDetails> = backend.list_sessions()?.into_iter().filter(|session| session.process_id == pid).collect();
+    if sessions.is_empty() {
+        return Err(AppError::ProcessNotFound { pid });
+    }
+    Ok(sessions)
+}
+
+// Spreads a balance over `channel_count` channels, 1.0 being the full level.
+// The first two channels are front left and front right in every layout Windows and pulse use, the others stay at full level.
+pub fn balance_volumes(balance: f32, channel_count: usize) -> Vec<f32> {
+    let balance = balance.clamp(-1.0, 1.0);
+    (0..channel_count)
+        .map(|channel| match channel {
+            0 if channel_count > 1 => (1.0 - balance).min(1.0),
+            1 => (1.0 + balance).min(1.0),
+            _ => 1.0, // mono has nothing to pan
+        })
+        .collect()
+}
+
+// the backend is shared between the tauri commands (through app.manage) and the monitor thread
+pub type SharedBackend = Arc<dyn AudioBackend>;
+
+// set this to a JSON fixture (see fixtures/mock_sessions.json) to run the app on fake sessions, handy for UI work
+pub const MOCK_FIXTURE_ENV: &str = "SOUND_PANEL_MOCK_FIXTURE";
+
+// picks the backend for the platform we are compiled for
+pub fn default_backend() -> SharedBackend {
+    if let Ok(path) = std::env::var(MOCK_FIXTURE_ENV) {
+        match mock::MockBackend::from_json_file(&path) {
+            Ok(backend) => return Arc::new(backend),
+            Err(e) => eprintln!("[AudioBackend] {}. Falling back to the platform backend.", e),
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        Arc::new(wasapi::WasapiBackend::new())
+    }
+    #[cfg(target_os = "linux")]
+    {
+        Arc::new(pulse::PulseBackend)
+    }
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        Arc::new(unsupported::UnsupportedBackend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balance_only_lowers_the_opposite_side() {
+        assert_eq!(balance_volumes(0.0, 2), vec![1.0, 1.0]);
+        assert_eq!(balance_volumes(-0.25, 2), vec![1.0, 0.75]);
+        assert_eq!(balance_volumes(1.0, 2), vec![0.0, 1.0]);
+        assert_eq!(balance_volumes(5.0, 6), vec![0.0, 1.0, 1.0, 1.0, 1.0, 1.0]); // clamped, surround channels untouched
+        assert_eq!(balance_volumes(0.5, 1), vec![1.0]);
+    }
+
+    #[test]
+    fn set_balance_keeps_the_loudest_channel_level() {
+        let backend = mock::MockBackend::with_sessions(vec![SessionDetails::test(10, "a")]);
+        backend.set_channel_volumes(10, "a", &[0.5, 0.5]).unwrap();
+
+        backend.set_balance(10, "a", 0.5).unwrap();
+
+        assert_eq!(backend.get_channel_volumes(10, "a").unwrap(), vec![0.25, 0.5]);
+    }
+
+    #[test]
+    fn set_process_volume_reaches_every_session_of_the_pid() {
+        let backend = mock::MockBackend::with_sessions(vec![SessionDetails::test(10, "a"), SessionDetails::test(11, "b"), SessionDetails::test(10, "c")]);
+
+        let applied = backend.set_process_volume(10, 0.3).unwrap();
+
+        assert_eq!(applied.iter().map(|a| a.session_uid.as_str()).collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!(backend.session("b").unwrap().session_volume, 1.0);
+        assert_eq!(backend.set_process_mute(99, true), Err(AppError::ProcessNotFound { pid: 99 }));
+    }
+}
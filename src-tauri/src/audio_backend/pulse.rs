@@ -0,0 +1,812 @@
+// PulseAudio / PipeWire implementation of the AudioBackend trait.
+// A sink-input (one playback stream of an app) is the Linux equivalent of a WASAPI audio session,
+// a source-output (one recording stream) the equivalent of a capture session.
+// We talk to the server through `pactl`, which works the same against pulseaudio and pipewire-pulse.
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    thread,
+};
+use serde_json::Value;
+use super::{session_id, AudioBackend, AudioLevels, ChannelVolumesPayload, DataFlow, DefaultDeviceChangedPayload, DeviceDetails, DeviceRole, DeviceState, IconSource, MasterVolume, SessionClosedPayload, SessionCloseReason, SessionDetails, SessionEvent, SessionEventHandler, SessionRenamedPayload, SessionState, SessionStatePayload, Subscription, VolumeChangedPayload};
+use crate::app_groups;
+use crate::error::AppError;
+use crate::process_info;
+
+// PA_VOLUME_NORM, the raw channel volume that means 100%
+const VOLUME_NORM: f64 = 65536.0;
+const DATA_FLOWS: [DataFlow; 2] = [DataFlow::Render, DataFlow::Capture];
+
+pub struct PulseBackend;
+
+// pulse calls playback streams sink-inputs and recording streams source-outputs, their devices sinks and sources.
+// pactl names its commands, list sections and events after these, e.g. `set-source-output-mute`
+fn stream_kind(data_flow: DataFlow) -> &'static str {
+    match data_flow {
+        DataFlow::Render => "sink-input",
+        DataFlow::Capture => "source-output",
+    }
+}
+
+fn device_kind(data_flow: DataFlow) -> &'static str {
+    match data_flow {
+        DataFlow::Render => "sink",
+        DataFlow::Capture => "source",
+    }
+}
+
+// uids look like "sink-input:42" (or "source-output:12") so they can't be mistaken for a pid
+fn session_uid(data_flow: DataFlow, index: u32) -> String {
+    format!("{}:{}", stream_kind(data_flow), index)
+}
+
+fn stream_index(uid: &str) -> Option<(DataFlow, u32)> {
+    DATA_FLOWS.into_iter().find_map(|data_flow| {
+        let index = uid.strip_prefix(stream_kind(data_flow))?.strip_prefix(':')?.parse().ok()?;
+        Some((data_flow, index))
+    })
+}
+
+// devices get the same treatment, "sink:1" or "source:2"
+fn device_id(data_flow: DataFlow, index: u64) -> String {
+    format!("{}:{}", device_kind(data_flow), index)
+}
+
+fn device_index(device_id: &str) -> Option<(DataFlow, u32)> {
+    DATA_FLOWS.into_iter().find_map(|data_flow| {
+        let index = device_id.strip_prefix(device_kind(data_flow))?.strip_prefix(':')?.parse().ok()?;
+        Some((data_flow, index))
+    })
+}
+
+fn raw_volume(volume: f32) -> u32 {
+    ((volume.clamp(0.0, 1.0) as f64) * VOLUME_NORM).round() as u32
+}
+
+fn run_pactl(args: &[&str]) -> Result<String, AppError> {
+    let output = Command::new("pactl")
+        .args(args)
+        .output()
+        .map_err(|e| AppError::backend(format!("Failed to run pactl: {}", e)))?;
+
+    if !output.status.success() {
+        return Err(AppError::backend(format!(
+            "pactl {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// the output of `pactl --format=json list <section>` is a JSON list of objects
+fn parse_list(json: &str, section: &str) -> Result<Vec<Value>, AppError> {
+    let value: Value = serde_json::from_str(json).map_err(|e| AppError::backend(format!("Failed to parse pactl output: {}", e)))?;
+    match value {
+        Value::Array(items) => Ok(items),
+        _ => Err(AppError::backend(format!("pactl output is not a list of {}", section))),
+    }
+}
+
+// the loudest channel, that is what pulseaudio itself reports as the stream volume
+fn parse_volume(volume: &Value) -> f32 {
+    volume
+        .as_object()
+        .map(|channels| {
+            channels
+                .values()
+                .filter_map(|channel| channel.get("value").and_then(Value::as_f64))
+                .fold(0.0, f64::max)
+        })
+        .map(|raw| (raw / VOLUME_NORM) as f32)
+        .unwrap_or(0.0)
+}
+
+// the level of every channel in the order of the channel map, e.g. "front-left,front-right"
+// (the volume object itself is keyed by channel name and serde_json doesn't keep its order)
+fn parse_channel_volumes(stream: &Value) -> Vec<f32> {
+    let Some(volume) = stream.get("volume") else { return Vec::new() };
+    stream
+        .get("channel_map")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|channel| volume.get(channel)?.get("value")?.as_f64())
+        .map(|raw| (raw / VOLUME_NORM) as f32)
+        .collect()
+}
+
+fn parse_stream(stream: &Value, data_flow: DataFlow) -> Option<SessionDetails> {
+    let index = stream.get("index")?.as_u64()? as u32;
+    let properties = stream.get("properties");
+    let property = |key: &str| {
+        properties
+            .and_then(|p| p.get(key))
+            .and_then(Value::as_str)
+            .filter(|s| !s.is_empty())
+    };
+
+    // pactl gives every property as a string, the pid included
+    let process_id = property("application.process.id")
+        .and_then(|pid| pid.parse().ok())
+        .unwrap_or(0);
+
+
+    Some(SessionDetails {
+        process_id,
+        session_uid: session_uid(data_flow, index),
+        display_name: property("application.name").map(str::to_string),
+        exe_name: property("application.process.binary").map(str::to_string),
+        // streams only carry a themed icon name ("application.icon_name"), not a file
+        icon_path: None,
+        // there is no grouping parameter in pulse, the app groups fall back to the executable
+        grouping_id: None,
+        group_key: String::new(), // filled in by list_stream_states with the app
+        app: None, // filled in by list_stream_states, the pid has to be a running process
+        // stream indexes start over with every stream, the binary's name is what stays
+        app_key: property("application.process.binary").map(session_id::app_key),
+        // event sounds are short streams of their own, there is no lasting session to control them by
+        is_system_sounds: false,
+        session_volume: stream.get("volume").map(parse_volume).unwrap_or(0.0),
+        is_muted: stream.get("mute").and_then(Value::as_bool).unwrap_or(false),
+        // a corked stream is paused, the closest thing to an inactive WASAPI session
+        state: if stream.get("corked").and_then(Value::as_bool).unwrap_or(false) { SessionState::Inactive } else { SessionState::Active },
+        // a sink-input has a "sink" field, a source-output a "source" field
+        device_id: stream.get(device_kind(data_flow)).and_then(Value::as_u64).map(|index| device_id(data_flow, index)).unwrap_or_default(),
+        data_flow,
+    })
+}
+
+// a stream and the level of each of its channels, the watcher compares both on 'change'
+type StreamState = (SessionDetails, Vec<f32>);
+
+// parses the output of `pactl --format=json list sink-inputs` (or source-outputs)
+fn parse_stream_states(json: &str, data_flow: DataFlow) -> Result<Vec<StreamState>, AppError> {
+    let streams = parse_list(json, stream_kind(data_flow))?;
+    Ok(streams.iter().filter_map(|stream| Some((parse_stream(stream, data_flow)?, parse_channel_volumes(stream)))).collect())
+}
+
+fn list_stream_states(data_flow: DataFlow) -> Result<Vec<StreamState>, AppError> {
+    let section = format!("{}s", stream_kind(data_flow));
+    let mut states = parse_stream_states(&run_pactl(&["--format=json", "list", &section])?, data_flow)?;
+    for (details, _) in states.iter_mut().filter(|(details, _)| details.process_id != 0) {
+        details.app = process_info::app_identity(details.process_id);
+    }
+    // after the app is known, a helper's streams go in its app's group
+    for (details, _) in states.iter_mut() {
+        details.group_key = app_groups::group_key(details);
+    }
+    Ok(states)
+}
+
+fn list_streams(data_flow: DataFlow) -> Result<Vec<SessionDetails>, AppError> {
+    Ok(list_stream_states(data_flow)?.into_iter().map(|(details, _)| details).collect())
+}
+
+// the stream behind a uid, only if it still belongs to `pid`
+fn find_owned_stream(pid: u32, uid: &str) -> Result<Option<StreamState>, AppError> {
+    let Some((data_flow, _)) = stream_index(uid) else { return Ok(None) };
+    Ok(list_stream_states(data_flow)?.into_iter().find(|(s, _)| s.process_id == pid && s.session_uid == uid))
+}
+
+// the stream's index and details, or SessionNotFound when `pid` no longer owns the uid
+fn owned_stream(pid: u32, uid: &str) -> Result<(u32, SessionDetails), AppError> {
+    let not_found = || AppError::SessionNotFound { uid: uid.to_string() };
+    let (data_flow, index) = stream_index(uid).ok_or_else(not_found)?;
+    let session = list_streams(data_flow)?.into_iter().find(|s| s.process_id == pid && s.session_uid == uid).ok_or_else(not_found)?;
+    Ok((index, session))
+}
+
+// what pulse applied, it rounds the volume to its own steps and the stream can vanish in between
+fn read_back(pid: u32, uid: &str) -> Result<VolumeChangedPayload, AppError> {
+    let (_, session) = owned_stream(pid, uid)?;
+    Ok(VolumeChangedPayload { session_uid: session.session_uid, volume: session.session_volume, is_muted: session.is_muted })
+}
+
+// playback streams first, then the recording ones
+fn list_all_stream_states() -> Result<Vec<StreamState>, AppError> {
+    let mut streams = list_stream_states(DataFlow::Render)?;
+    streams.extend(list_stream_states(DataFlow::Capture)?);
+    Ok(streams)
+}
+
+// every sink has a monitor source that records what it plays, those are not microphones
+fn is_monitor(device: &Value) -> bool {
+    device.get("monitor_of_sink").and_then(Value::as_str).is_some_and(|sink| !sink.is_empty() && sink != "n/a")
+}
+
+// the sinks (or sources) we show, with their index
+fn devices_of(devices: &[Value]) -> impl Iterator<Item = (u64, &Value)> {
+    devices.iter().filter(|device| !is_monitor(device)).filter_map(|device| Some((device.get("index")?.as_u64()?, device)))
+}
+
+// parses the output of `pactl --format=json list sinks` (or sources), `default_device` is the name from `pactl get-default-sink`
+fn parse_devices(json: &str, data_flow: DataFlow, default_device: &str) -> Result<Vec<DeviceDetails>, AppError> {
+    let devices = parse_list(json, device_kind(data_flow))?;
+    Ok(devices_of(&devices)
+        .map(|(index, device)| {
+            let name = device.get("name").and_then(Value::as_str).unwrap_or_default();
+            // pulse has a single default sink (and source), it is the default for every role
+            let is_default = name == default_device;
+            DeviceDetails {
+                id: device_id(data_flow, index),
+                name: device.get("description").and_then(Value::as_str).filter(|d| !d.is_empty()).unwrap_or(name).to_string(),
+                // pactl only lists devices that exist, a suspended one is just idle
+                state: DeviceState::Active,
+                data_flow,
+                is_default_console: is_default,
+                is_default_multimedia: is_default,
+                is_default_communications: is_default,
+            }
+        })
+        .collect())
+}
+
+fn list_devices_of(data_flow: DataFlow) -> Result<Vec<DeviceDetails>, AppError> {
+    // get-default-sink fails on servers without a default, that just means no device is marked
+    let default_device = run_pactl(&[&format!("get-default-{}", device_kind(data_flow))]).unwrap_or_default();
+    let section = format!("{}s", device_kind(data_flow));
+    parse_devices(&run_pactl(&["--format=json", "list", &section])?, data_flow, default_device.trim())
+}
+
+fn list_all_devices() -> Result<Vec<DeviceDetails>, AppError> {
+    let mut devices = list_devices_of(DataFlow::Render)?;
+    devices.extend(list_devices_of(DataFlow::Capture)?);
+    Ok(devices)
+}
+
+fn default_device_id(data_flow: DataFlow) -> Option<String> {
+    list_devices_of(data_flow).ok()?.into_iter().find(|d| d.is_default_console).map(|d| d.id)
+}
+
+// the sink (or source) volume is the master volume of the device, parsed from the same list as the devices
+fn parse_master_volumes(json: &str, data_flow: DataFlow) -> Result<Vec<MasterVolume>, AppError> {
+    let devices = parse_list(json, device_kind(data_flow))?;
+    Ok(devices_of(&devices)
+        .map(|(index, device)| MasterVolume {
+            device_id: device_id(data_flow, index),
+            volume: device.get("volume").map(parse_volume).unwrap_or(0.0),
+            is_muted: device.get("mute").and_then(Value::as_bool).unwrap_or(false),
+        })
+        .collect())
+}
+
+fn list_master_volumes(data_flow: DataFlow) -> Result<Vec<MasterVolume>, AppError> {
+    let section = format!("{}s", device_kind(data_flow));
+    parse_master_volumes(&run_pactl(&["--format=json", "list", &section])?, data_flow)
+}
+
+fn find_master_volume(device_id: &str) -> Result<MasterVolume, AppError> {
+    let (data_flow, _) = device_index(device_id).ok_or_else(|| AppError::DeviceUnavailable { device_id: device_id.to_string() })?;
+    list_master_volumes(data_flow)?
+        .into_iter()
+        .find(|m| m.device_id == device_id)
+        .ok_or_else(|| AppError::DeviceUnavailable { device_id: device_id.to_string() })
+}
+
+#[derive(Debug, PartialEq)]
+enum PulseEvent {
+    // sink-inputs and source-outputs (sessions)
+    New(DataFlow, u32),
+    Change(DataFlow, u32),
+    Remove(DataFlow, u32),
+    // sinks and sources (devices)
+    DeviceNew(DataFlow, u32),
+    DeviceChange(DataFlow, u32),
+    DeviceRemove(DataFlow, u32),
+    // the server settings changed, the default sink or source may have moved
+    ServerChange,
+}
+
+// parses one line of `pactl subscribe`, e.g. "Event 'new' on sink-input #42"
+// events about other facilities (clients, modules...) are ignored
+fn parse_subscribe_line(line: &str) -> Option<PulseEvent> {
+    let rest = line.trim().strip_prefix("Event '")?;
+    let (kind, rest) = rest.split_once('\'')?;
+    let rest = rest.trim().strip_prefix("on ")?;
+    for data_flow in DATA_FLOWS {
+        if let Some(index) = rest.strip_prefix(stream_kind(data_flow)).and_then(|r| r.strip_prefix(" #")) {
+            let index = index.parse().ok()?;
+            return match kind {
+                "new" => Some(PulseEvent::New(data_flow, index)),
+                "change" => Some(PulseEvent::Change(data_flow, index)),
+                "remove" => Some(PulseEvent::Remove(data_flow, index)),
+                _ => None,
+            };
+        }
+        if let Some(index) = rest.strip_prefix(device_kind(data_flow)).and_then(|r| r.strip_prefix(" #")) {
+            let index = index.parse().ok()?;
+            return match kind {
+                "new" => Some(PulseEvent::DeviceNew(data_flow, index)),
+                "change" => Some(PulseEvent::DeviceChange(data_flow, index)),
+                "remove" => Some(PulseEvent::DeviceRemove(data_flow, index)),
+                _ => None,
+            };
+        }
+    }
+    match (kind, rest.starts_with("server")) {
+        ("change", true) => Some(PulseEvent::ServerChange),
+        _ => None,
+    }
+}
+
+// a 'change' event doesn't say what changed, so we compare against what we knew about the stream
+fn diff_sessions(old: &SessionDetails, new: &SessionDetails) -> Vec<SessionEvent> {
+    // moved to another sink or source: it leaves the old device's list and shows up in the new one's, with everything it has now
+    if old.device_id != new.device_id {
+        return vec![
+            SessionEvent::Closed(SessionClosedPayload { session_uid: old.session_uid.clone(), reason: SessionCloseReason::Moved }),
+            SessionEvent::Created(new.clone()),
+        ];
+    }
+    let mut events = Vec::new();
+    if old.session_volume != new.session_volume || old.is_muted != new.is_muted {
+        events.push(SessionEvent::VolumeChanged(VolumeChangedPayload {
+            session_uid: new.session_uid.clone(),
+            volume: new.session_volume,
+            is_muted: new.is_muted,
+        }));
+    }
+    if old.display_name != new.display_name {
+        events.push(SessionEvent::Renamed(SessionRenamedPayload {
+            session_uid: new.session_uid.clone(),
+            display_name: new.display_name.clone(),
+        }));
+    }
+    if old.state != new.state {
+        events.push(SessionEvent::StateChanged(SessionStatePayload {
+            session_uid: new.session_uid.clone(),
+            state: new.state,
+        }));
+    }
+    events
+}
+
+fn find_stream(data_flow: DataFlow, index: u32) -> Option<StreamState> {
+    let uid = session_uid(data_flow, index);
+    list_stream_states(data_flow).ok()?.into_iter().find(|(s, _)| s.session_uid == uid)
+}
+
+// what the watcher knows about the server, 'change' events are compared against it
+#[derive(Default)]
+struct WatchState {
+    sessions: HashMap<String, StreamState>, // keyed by uid
+    default_devices: HashMap<DataFlow, Option<String>>, // the device id of the default sink and source
+    master_volumes: HashMap<String, MasterVolume>, // keyed by device id
+}
+
+impl WatchState {
+    fn load() -> Result<Self, AppError> {
+        Ok(Self {
+            sessions: list_all_stream_states()?.into_iter().map(|(s, channels)| (s.session_uid.clone(), (s, channels))).collect(),
+            default_devices: DATA_FLOWS.into_iter().map(|data_flow| (data_flow, default_device_id(data_flow))).collect(),
+            master_volumes: DATA_FLOWS
+                .into_iter()
+                .flat_map(|data_flow| list_master_volumes(data_flow).unwrap_or_default())
+                .map(|m| (m.device_id.clone(), m))
+                .collect(),
+        })
+    }
+}
+
+// reads `pactl subscribe` until the process is killed by dropping the Subscription
+fn session_watch_loop(on_event: SessionEventHandler, events: impl BufRead, mut state: WatchState) {
+    for line in events.lines() {
+        let Ok(line) = line else { break };
+
+        match parse_subscribe_line(&line) {
+            Some(PulseEvent::New(data_flow, index)) => {
+                if state.sessions.contains_key(&session_uid(data_flow, index)) {
+                    continue;
+                }
+                if let Some((details, channels)) = find_stream(data_flow, index) {
+                    state.sessions.insert(details.session_uid.clone(), (details.clone(), channels));
+                    on_event(SessionEvent::Created(details));
+                }
+            }
+            Some(PulseEvent::Change(data_flow, index)) => {
+                let Some((details, channels)) = find_stream(data_flow, index) else { continue };
+                match state.sessions.insert(details.session_uid.clone(), (details.clone(), channels.clone())) {
+                    Some((old, old_channels)) => {
+                        diff_sessions(&old, &details).into_iter().for_each(|event| on_event(event));
+                        if old_channels != channels {
+                            on_event(SessionEvent::ChannelsChanged(ChannelVolumesPayload { session_uid: details.session_uid, volumes: channels }));
+                        }
+                    }
+                    // we missed the 'new' event (it raced with the initial listing), report it now
+                    None => on_event(SessionEvent::Created(details)),
+                }
+            }
+            Some(PulseEvent::Remove(data_flow, index)) => {
+                let Some((closed, _)) = state.sessions.remove(&session_uid(data_flow, index)) else { continue };
+                // pulse doesn't say why, the stream was destroyed by its app (or with it)
+                on_event(SessionEvent::Closed(SessionClosedPayload { session_uid: closed.session_uid, reason: SessionCloseReason::Expired }));
+            }
+            Some(PulseEvent::DeviceNew(data_flow, index)) => {
+                let id = device_id(data_flow, index as u64);
+                // monitor sources are not in the list, so they are never reported
+                if let Some(device) = list_devices_of(data_flow).ok().and_then(|devices| devices.into_iter().find(|d| d.id == id)) {
+                    if let Ok(master) = find_master_volume(&id) {
+                        state.master_volumes.insert(id, master);
+                    }
+                    on_event(SessionEvent::DeviceAdded(device));
+                }
+            }
+            // devices get 'change' events for a lot of things (suspended, ports...), only the volume matters here
+            Some(PulseEvent::DeviceChange(data_flow, index)) => {
+                let Ok(master) = find_master_volume(&device_id(data_flow, index as u64)) else { continue };
+                if state.master_volumes.get(&master.device_id) != Some(&master) {
+                    state.master_volumes.insert(master.device_id.clone(), master.clone());
+                    on_event(SessionEvent::MasterVolumeChanged(master));
+                }
+            }
+            // the server moves the streams of a removed device to another one, they get 'change' events of their own
+            Some(PulseEvent::DeviceRemove(data_flow, index)) => {
+                let id = device_id(data_flow, index as u64);
+                if state.master_volumes.remove(&id).is_some() {
+                    on_event(SessionEvent::DeviceRemoved(id));
+                }
+            }
+            Some(PulseEvent::ServerChange) => {
+                for data_flow in DATA_FLOWS {
+                    let current = default_device_id(data_flow);
+                    if state.default_devices.get(&data_flow) == Some(&current) {
+                        continue;
+                    }
+                    state.default_devices.insert(data_flow, current.clone());
+                    // one default device means one change for every role
+                    for role in [DeviceRole::Console, DeviceRole::Multimedia, DeviceRole::Communications] {
+                        on_event(SessionEvent::DefaultDeviceChanged(DefaultDeviceChangedPayload { device_id: current.clone(), data_flow, role }));
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+impl AudioBackend for PulseBackend {
+    fn list_sessions(&self) -> Result<Vec<SessionDetails>, AppError> {
+        Ok(list_all_stream_states()?.into_iter().map(|(details, _)| details).collect())
+    }
+
+    fn list_devices(&self) -> Result<Vec<DeviceDetails>, AppError> {
+        list_all_devices()
+    }
+
+    fn set_volume(&self, pid: u32, uid: &str, volume: f32) -> Result<VolumeChangedPayload, AppError> {
+        // same rule as on Windows: only touch the stream if both the pid and the uid still match
+        let (index, session) = owned_stream(pid, uid)?;
+        let command = format!("set-{}-volume", stream_kind(session.data_flow));
+        run_pactl(&[&command, &index.to_string(), &raw_volume(volume).to_string()])?;
+        read_back(pid, uid)
+    }
+
+    fn set_mute(&self, pid: u32, uid: &str, mute: bool) -> Result<VolumeChangedPayload, AppError> {
+        let (index, session) = owned_stream(pid, uid)?;
+        if session.is_muted == mute {
+            return Ok(VolumeChangedPayload { session_uid: session.session_uid, volume: session.session_volume, is_muted: mute });
+        }
+        let command = format!("set-{}-mute", stream_kind(session.data_flow));
+        run_pactl(&[&command, &index.to_string(), if mute { "1" } else { "0" }])?;
+        read_back(pid, uid)
+    }
+
+    fn get_channel_volumes(&self, pid: u32, uid: &str) -> Result<Vec<f32>, AppError> {
+        let (_, channels) = find_owned_stream(pid, uid)?.ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+        Ok(channels)
+    }
+
+    fn set_channel_volumes(&self, pid: u32, uid: &str, volumes: &[f32]) -> Result<(), AppError> {
+        let not_found = || AppError::SessionNotFound { uid: uid.to_string() };
+        let (data_flow, index) = stream_index(uid).ok_or_else(not_found)?;
+        let (_, channels) = find_owned_stream(pid, uid)?.ok_or_else(not_found)?;
+        if volumes.len() != channels.len() {
+            return Err(AppError::InvalidArgument(format!("Session {} has {} channels, got {} volumes", uid, channels.len(), volumes.len())));
+        }
+        // pactl takes one raw volume per channel after the index
+        let command = format!("set-{}-volume", stream_kind(data_flow));
+        let index = index.to_string();
+        let raw_volumes: Vec<String> = volumes.iter().map(|volume| raw_volume(*volume).to_string()).collect();
+        let mut args = vec![command.as_str(), index.as_str()];
+        args.extend(raw_volumes.iter().map(String::as_str));
+        run_pactl(&args)?;
+        Ok(())
+    }
+
+    fn get_master_volume(&self, device_id: &str) -> Result<MasterVolume, AppError> {
+        find_master_volume(device_id)
+    }
+
+    fn set_master_volume(&self, device_id: &str, volume: f32) -> Result<(), AppError> {
+        let (data_flow, index) = device_index(device_id).ok_or_else(|| AppError::DeviceUnavailable { device_id: device_id.to_string() })?;
+        // a well formed id of an unplugged device would only fail in pactl, as a generic error
+        find_master_volume(device_id)?;
+        let command = format!("set-{}-volume", device_kind(data_flow));
+        run_pactl(&[&command, &index.to_string(), &raw_volume(volume).to_string()])?;
+        Ok(())
+    }
+
+    fn set_master_mute(&self, device_id: &str, mute: bool) -> Result<(), AppError> {
+        let (data_flow, index) = device_index(device_id).ok_or_else(|| AppError::DeviceUnavailable { device_id: device_id.to_string() })?;
+        if find_master_volume(device_id)?.is_muted != mute {
+            let command = format!("set-{}-mute", device_kind(data_flow));
+            run_pactl(&[&command, &index.to_string(), if mute { "1" } else { "0" }])?;
+        }
+        Ok(())
+    }
+
+    // streams have no icon file, only the executable (through /proc) can say which app it is
+    fn icon_source(&self, pid: u32, uid: &str) -> Result<IconSource, AppError> {
+        let (session, _) = find_owned_stream(pid, uid)?.ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+        Ok(IconSource { exe_path: process_info::image_path(session.process_id), icon_path: session.icon_path })
+    }
+
+    fn read_levels(&self) -> Result<AudioLevels, AppError> {
+        // pactl can't sample peaks (that takes a monitor stream per sink), an empty read would look like silence
+        Err(AppError::Unsupported("Peak meters are not available with PulseAudio".to_string()))
+    }
+
+    fn subscribe(&self, on_event: SessionEventHandler) -> Result<Subscription, AppError> {
+        // start listening before taking the initial listing so nothing falls in between,
+        // streams that show up in both are filtered out by the watcher
+        let mut child = Command::new("pactl")
+            .arg("subscribe")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| AppError::backend(format!("Failed to run pactl subscribe: {}", e)))?;
+        let stdout = child.stdout.take().ok_or_else(|| AppError::backend("pactl subscribe has no stdout"))?;
+
+        let state = match WatchState::load() {
+            Ok(state) => state,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+
+        let watcher = thread::spawn(move || session_watch_loop(on_event, BufReader::new(stdout), state));
+
+        Ok(Subscription::new(move || {
+            // killing pactl closes its stdout which ends the watcher loop
+            let _ = child.kill();
+            let _ = child.wait();
+            let _ = watcher.join();
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc, Arc};
+
+    const FIXTURE: &str = include_str!("../../fixtures/pactl_sink_inputs.json");
+
+    fn parse_streams(json: &str, data_flow: DataFlow) -> Result<Vec<SessionDetails>, AppError> {
+        Ok(parse_stream_states(json, data_flow)?.into_iter().map(|(details, _)| details).collect())
+    }
+
+    #[test]
+    fn maps_sink_inputs_to_sessions() {
+        let sessions = parse_streams(FIXTURE, DataFlow::Render).unwrap();
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].process_id, 2211);
+        assert_eq!(sessions[0].session_uid, "sink-input:42");
+        assert_eq!(sessions[0].display_name.as_deref(), Some("Firefox"));
+        assert_eq!(sessions[0].exe_name.as_deref(), Some("firefox"));
+        assert_eq!(sessions[0].app_key.as_deref(), Some("app:firefox"));
+        assert_eq!(sessions[0].session_volume, 0.5);
+        assert!(!sessions[0].is_muted);
+        assert_eq!(sessions[0].state, SessionState::Active);
+        assert_eq!(sessions[0].device_id, "sink:1");
+        assert_eq!(sessions[0].data_flow, DataFlow::Render);
+        assert_eq!(parse_stream_states(FIXTURE, DataFlow::Render).unwrap()[0].1, vec![0.5, 0.5]);
+
+        // no application.name and no pid: only the binary name and pid 0
+        assert_eq!(sessions[1].process_id, 0);
+        assert_eq!(sessions[1].display_name, None);
+        assert_eq!(sessions[1].exe_name.as_deref(), Some("mpv"));
+        assert!(sessions[1].is_muted);
+        assert_eq!(sessions[1].state, SessionState::Inactive);
+    }
+
+    #[test]
+    fn maps_source_outputs_to_capture_sessions() {
+        let sessions = parse_streams(include_str!("../../fixtures/pactl_source_outputs.json"), DataFlow::Capture).unwrap();
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_uid, "source-output:12");
+        assert_eq!(sessions[0].display_name.as_deref(), Some("Zoom"));
+        assert_eq!(sessions[0].device_id, "source:2");
+        assert_eq!(sessions[0].data_flow, DataFlow::Capture);
+    }
+
+    #[test]
+    fn maps_sinks_to_devices() {
+        let devices = parse_devices(include_str!("../../fixtures/pactl_sinks.json"), DataFlow::Render, "alsa_output.usb-headset.analog-stereo").unwrap();
+
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].id, "sink:1");
+        assert_eq!(devices[0].name, "Built-in Audio Analog Stereo");
+        assert!(!devices[0].is_default_console);
+        assert_eq!(devices[1].id, "sink:3");
+        assert!(devices[1].is_default_console && devices[1].is_default_multimedia && devices[1].is_default_communications);
+        assert_eq!(devices[1].state, DeviceState::Active);
+    }
+
+    #[test]
+    fn maps_sources_to_input_devices_without_the_monitors() {
+        let sources = include_str!("../../fixtures/pactl_sources.json");
+        let devices = parse_devices(sources, DataFlow::Capture, "alsa_input.pci-0000_00_1f.3.analog-stereo").unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].id, "source:2");
+        assert_eq!(devices[0].data_flow, DataFlow::Capture);
+        assert!(devices[0].is_default_communications);
+        assert_eq!(parse_master_volumes(sources, DataFlow::Capture).unwrap(), vec![MasterVolume { device_id: "source:2".to_string(), volume: 0.75, is_muted: true }]);
+    }
+
+    #[test]
+    fn maps_sink_volumes_to_master_volumes() {
+        let volumes = parse_master_volumes(include_str!("../../fixtures/pactl_sinks.json"), DataFlow::Render).unwrap();
+
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(volumes[0], MasterVolume { device_id: "sink:1".to_string(), volume: 1.0, is_muted: false });
+        assert_eq!(volumes[1].device_id, "sink:3");
+        assert_eq!(volumes[1].volume, (42597.0 / 65536.0) as f32);
+        assert_eq!(device_index(&volumes[1].device_id), Some((DataFlow::Render, 3)));
+        assert_eq!(device_index("source:2"), Some((DataFlow::Capture, 2)));
+        assert_eq!(device_index("sink-input:2"), None);
+        assert_eq!(device_index("mock-speakers"), None);
+    }
+
+    #[test]
+    fn channel_volumes_follow_the_channel_map() {
+        let stream = serde_json::json!({
+            "channel_map": "front-right,front-left",
+            "volume": {
+                "front-left": { "value": 32768 },
+                "front-right": { "value": 65536 }
+            }
+        });
+        assert_eq!(parse_channel_volumes(&stream), vec![1.0, 0.5]);
+    }
+
+    #[test]
+    fn uses_the_loudest_channel_as_the_volume() {
+        let volume = serde_json::json!({
+            "front-left": { "value": 32768 },
+            "front-right": { "value": 65536 }
+        });
+        assert_eq!(parse_volume(&volume), 1.0);
+    }
+
+    #[test]
+    fn parses_stream_subscribe_lines() {
+        assert_eq!(parse_subscribe_line("Event 'new' on sink-input #42"), Some(PulseEvent::New(DataFlow::Render, 42)));
+        assert_eq!(parse_subscribe_line("Event 'change' on sink-input #7\n"), Some(PulseEvent::Change(DataFlow::Render, 7)));
+        assert_eq!(parse_subscribe_line("Event 'remove' on sink-input #3"), Some(PulseEvent::Remove(DataFlow::Render, 3)));
+        assert_eq!(parse_subscribe_line("Event 'new' on source-output #12"), Some(PulseEvent::New(DataFlow::Capture, 12)));
+        assert_eq!(parse_subscribe_line("Event 'new' on client #12"), None);
+    }
+
+    #[test]
+    fn parses_device_subscribe_lines() {
+        assert_eq!(parse_subscribe_line("Event 'new' on sink #5"), Some(PulseEvent::DeviceNew(DataFlow::Render, 5)));
+        assert_eq!(parse_subscribe_line("Event 'change' on sink #0"), Some(PulseEvent::DeviceChange(DataFlow::Render, 0)));
+        assert_eq!(parse_subscribe_line("Event 'remove' on sink #5"), Some(PulseEvent::DeviceRemove(DataFlow::Render, 5)));
+        assert_eq!(parse_subscribe_line("Event 'new' on source #2"), Some(PulseEvent::DeviceNew(DataFlow::Capture, 2)));
+        assert_eq!(parse_subscribe_line("Event 'change' on server #4294967295"), Some(PulseEvent::ServerChange));
+        assert_eq!(parse_subscribe_line("Event 'new' on card #2"), None);
+    }
+
+    #[test]
+    fn uids_round_trip_to_stream_indexes() {
+        assert_eq!(stream_index(&session_uid(DataFlow::Render, 42)), Some((DataFlow::Render, 42)));
+        assert_eq!(stream_index(&session_uid(DataFlow::Capture, 12)), Some((DataFlow::Capture, 12)));
+        assert_eq!(stream_index("{0.0.0.00000000}|chrome.exe"), None);
+    }
+
+    #[test]
+    fn changes_are_reported_like_the_wasapi_listeners() {
+        let old = parse_streams(FIXTURE, DataFlow::Render).unwrap().remove(0);
+        let mut new = old.clone();
+        new.session_volume = 0.75;
+        new.state = SessionState::Inactive;
+        new.display_name = Some("Firefox Nightly".to_string());
+
+        assert_eq!(
+            diff_sessions(&old, &new),
+            vec![
+                SessionEvent::VolumeChanged(VolumeChangedPayload { session_uid: "sink-input:42".to_string(), volume: 0.75, is_muted: false }),
+                SessionEvent::Renamed(SessionRenamedPayload { session_uid: "sink-input:42".to_string(), display_name: Some("Firefox Nightly".to_string()) }),
+                SessionEvent::StateChanged(SessionStatePayload { session_uid: "sink-input:42".to_string(), state: SessionState::Inactive }),
+            ]
+        );
+        assert!(diff_sessions(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn a_stream_moved_to_another_sink_is_closed_and_created_there() {
+        let old = parse_streams(FIXTURE, DataFlow::Render).unwrap().remove(0);
+        let mut new = old.clone();
+        new.device_id = "sink:7".to_string();
+        new.session_volume = 0.5;
+
+        assert_eq!(
+            diff_sessions(&old, &new),
+            vec![
+                SessionEvent::Closed(SessionClosedPayload { session_uid: "sink-input:42".to_string(), reason: SessionCloseReason::Moved }),
+                SessionEvent::Created(new.clone()),
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_events_close_known_sessions_only() {
+        let state = WatchState {
+            sessions: parse_stream_states(FIXTURE, DataFlow::Render).unwrap().into_iter().map(|(s, channels)| (s.session_uid.clone(), (s, channels))).collect(),
+            master_volumes: parse_master_volumes(include_str!("../../fixtures/pactl_sinks.json"), DataFlow::Render).unwrap().into_iter().map(|m| (m.device_id.clone(), m)).collect(),
+            ..Default::default()
+        };
+        let (sender, receiver) = mpsc::channel();
+        let on_event: SessionEventHandler = Arc::new(move |event| {
+            let _ = sender.send(event);
+        });
+
+        // source #0 is the monitor of sink #1, it goes away with it but was never reported as a device
+        let lines = "Event 'remove' on sink-input #99\nEvent 'remove' on sink-input #42\nEvent 'remove' on sink #1\nEvent 'remove' on source #0\n";
+        session_watch_loop(on_event, lines.as_bytes(), state);
+
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            SessionEvent::Closed(SessionClosedPayload { session_uid: "sink-input:42".to_string(), reason: SessionCloseReason::Expired })
+        );
+        assert_eq!(receiver.try_recv().unwrap(), SessionEvent::DeviceRemoved("sink:1".to_string()));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    // Needs a running pulseaudio or pipewire-pulse server, a null sink is enough:
+    //   pulseaudio --daemonize --exit-idle-time=-1 && pactl load-module module-null-sink
+    //   cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn reports_a_real_playback_stream() {
+        let (sender, receiver) = mpsc::channel();
+        let _subscription = PulseBackend
+            .subscribe(Arc::new(move |event| {
+                let _ = sender.send(event);
+            }))
+            .unwrap();
+
+        let mut player = Command::new("pacat")
+            .arg("--playback")
+            .stdin(std::fs::File::open("/dev/zero").unwrap())
+            .spawn()
+            .unwrap();
+        let pid = player.id();
+
+        // waits for the first event of the player's stream that matches, skipping the others
+        let wait_for = |matches: &dyn Fn(&SessionEvent) -> bool| loop {
+            let event = receiver.recv_timeout(std::time::Duration::from_secs(5)).expect("expected event never arrived");
+            if matches(&event) {
+                break event;
+            }
+        };
+
+        let SessionEvent::Created(created) = wait_for(&|e| matches!(e, SessionEvent::Created(d) if d.process_id == pid)) else { unreachable!() };
+        let uid = created.session_uid.clone();
+
+        assert_eq!(PulseBackend.set_volume(pid, &uid, 0.25).unwrap().volume, 0.25);
+        wait_for(&|e| matches!(e, SessionEvent::VolumeChanged(p) if p.session_uid == uid && p.volume == 0.25));
+
+        player.kill().unwrap();
+        let _ = player.wait();
+        wait_for(&|e| matches!(e, SessionEvent::Closed(p) if p.session_uid == uid));
+    }
+}
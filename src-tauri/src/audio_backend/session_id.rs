@@ -0,0 +1,129 @@
+// The pieces of a WASAPI session instance identifier, the string sessions are known by (`uid`).
+// It looks like "{0.0.0.00000000}.{endpoint guid}|\Device\HarddiskVolume3\Apps\Spotify.exe%b{session guid}|1%b4120":
+// the device the session plays to, the executable and the session GUID the app passed, then the instance
+// (a counter and the pid). Only the instance changes when the app restarts, the app key is built without it.
+//
+// App keys have the same format on every backend: "app:" and the executable's file name in lowercase
+// ("app:spotify.exe" on Windows, "app:firefox" on Linux), or "system-sounds". So a setting stored under one
+// still matches when the app is started from another folder, and the mock and pulse keys look like the real ones.
+use super::SessionDetails;
+
+// what the system sounds session has instead of an executable
+const SYSTEM_SOUNDS_PATH: &str = "#";
+pub const SYSTEM_SOUNDS_APP_KEY: &str = "system-sounds";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionIdentifier {
+    pub device_id: String,
+    pub app_path: Option<String>, // None for the system sounds session
+    pub session_guid: Option<String>, // None when the app didn't pick one (the all-zero GUID)
+    pub instance: Option<String>, // "1%b4120", gone from session identifiers (as opposed to instance identifiers)
+}
+
+impl SessionIdentifier {
+    // None for strings that aren't session identifiers, the mock and pulse uids for example
+    pub fn parse(identifier: &str) -> Option<Self> {
+        let mut parts = identifier.splitn(3, '|');
+        let device_id = parts.next().filter(|device_id| device_id.starts_with('{'))?;
+        let (app_path, session_guid) = parts.next()?.rsplit_once("%b")?;
+        Some(Self {
+            device_id: device_id.to_string(),
+            app_path: (app_path != SYSTEM_SOUNDS_PATH).then(|| app_path.to_string()),
+            session_guid: Some(session_guid).filter(|guid| !is_null_guid(guid)).map(str::to_string),
+            instance: parts.next().map(str::to_string),
+        })
+    }
+
+    // the same for every run of the app, on every device
+    pub fn app_key(&self) -> String {
+        match &self.app_path {
+            Some(app_path) => app_key(app_path),
+            None => SYSTEM_SOUNDS_APP_KEY.to_string(),
+        }
+    }
+}
+
+// takes a full path (WASAPI's "\Device\HarddiskVolume3\...\Spotify.exe") or only the executable's name (pulse, the mock)
+// names are compared the way Windows does, without case
+pub fn app_key(app_path: &str) -> String {
+    let exe_name = app_path.rsplit(['\\', '/']).next().unwrap_or(app_path);
+    format!("app:{}", exe_name.to_lowercase())
+}
+
+// the key a session's settings are stored under, from its identifier or else from its executable
+pub fn session_app_key(session: &SessionDetails) -> Option<String> {
+    if session.is_system_sounds {
+        return Some(SYSTEM_SOUNDS_APP_KEY.to_string());
+    }
+    match SessionIdentifier::parse(&session.session_uid) {
+        Some(identifier) => Some(identifier.app_key()),
+        None => session.exe_name.as_deref().map(app_key),
+    }
+}
+
+fn is_null_guid(guid: &str) -> bool {
+    guid.chars().all(|c| matches!(c, '{' | '}' | '-' | '0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPOTIFY: &str = "{0.0.0.00000000}.{5c3ba6b6-87d4-4f0a-9a56-0f1a8d7e2c11}|\\Device\\HarddiskVolume3\\Users\\me\\AppData\\Roaming\\Spotify\\Spotify.exe%b{00000000-0000-0000-0000-000000000000}|1%b4120";
+
+    #[test]
+    fn splits_an_instance_identifier() {
+        let identifier = SessionIdentifier::parse(SPOTIFY).unwrap();
+
+        assert_eq!(identifier.device_id, "{0.0.0.00000000}.{5c3ba6b6-87d4-4f0a-9a56-0f1a8d7e2c11}");
+        assert_eq!(identifier.app_path.as_deref(), Some("\\Device\\HarddiskVolume3\\Users\\me\\AppData\\Roaming\\Spotify\\Spotify.exe"));
+        assert_eq!(identifier.session_guid, None);
+        assert_eq!(identifier.instance.as_deref(), Some("1%b4120"));
+    }
+
+    #[test]
+    fn the_app_key_survives_a_restart() {
+        let restarted = SPOTIFY.replace("|1%b4120", "|1%b9876");
+        let other_device = SPOTIFY.replace("5c3ba6b6", "77777777");
+
+        let key = SessionIdentifier::parse(SPOTIFY).unwrap().app_key();
+        assert_eq!(key, "app:spotify.exe");
+        assert_eq!(SessionIdentifier::parse(&restarted).unwrap().app_key(), key);
+        assert_eq!(SessionIdentifier::parse(&other_device).unwrap().app_key(), key);
+    }
+
+    #[test]
+    fn recognizes_system_sounds_and_app_guids() {
+        let system = SessionIdentifier::parse("{0.0.0.00000000}.{5c3ba6b6}|#%b{A9EF3FD9-4240-455E-A4D5-F2B3301887B2}|1%b#").unwrap();
+        assert_eq!(system.app_path, None);
+        assert_eq!(system.session_guid.as_deref(), Some("{A9EF3FD9-4240-455E-A4D5-F2B3301887B2}"));
+        assert_eq!(system.app_key(), SYSTEM_SOUNDS_APP_KEY);
+
+        // a session identifier has no instance part
+        let session = SessionIdentifier::parse("{0.0.0.00000000}.{5c3ba6b6}|\\Device\\HarddiskVolume3\\a.exe%b{00000000-0000-0000-0000-000000000000}").unwrap();
+        assert_eq!(session.instance, None);
+
+        assert_eq!(SessionIdentifier::parse("mock|spotify.exe|1"), None);
+        assert_eq!(SessionIdentifier::parse("sink-input:12"), None);
+    }
+
+    #[test]
+    fn every_backend_gets_the_same_app_key_format() {
+        // WASAPI: an instance identifier, a session identifier and a drive letter path
+        let instance = SessionIdentifier::parse(SPOTIFY).unwrap().app_key();
+        let session = SessionIdentifier::parse("{0.0.0.00000000}.{5c3ba6b6}|\\Device\\HarddiskVolume3\\Spotify\\Spotify.exe%b{00000000-0000-0000-0000-000000000000}").unwrap();
+        assert_eq!(instance, "app:spotify.exe");
+        assert_eq!(session.app_key(), instance);
+        assert_eq!(app_key("C:\\Program Files\\Spotify\\SPOTIFY.EXE"), instance);
+
+        // pulse: the stream's "application.process.binary", sometimes a path
+        assert_eq!(app_key("firefox"), "app:firefox");
+        assert_eq!(app_key("/usr/lib/firefox/firefox"), "app:firefox");
+
+        // the mock and fixtures: the exe name of a uid that isn't a WASAPI identifier
+        let mut mock = SessionDetails::test(10, "mock-spotify").with_exe("Spotify.exe");
+        assert_eq!(session_app_key(&mock).as_deref(), Some("app:spotify.exe"));
+        mock.is_system_sounds = true;
+        assert_eq!(session_app_key(&mock).as_deref(), Some(SYSTEM_SOUNDS_APP_KEY));
+    }
+}
@@ -0,0 +1,59 @@
+// Fallback for platforms without a native backend yet.
+// It lets the crate build and run (with an empty mixer) instead of failing to compile.
+// There are no sessions and no devices, so every lookup ends in not found.
+use crate::error::AppError;
+use super::{AudioBackend, AudioLevels, DeviceDetails, IconSource, MasterVolume, SessionDetails, SessionEventHandler, Subscription, VolumeChangedPayload};
+
+pub struct UnsupportedBackend;
+
+impl AudioBackend for UnsupportedBackend {
+    fn list_sessions(&self) -> Result<Vec<SessionDetails>, AppError> {
+        Ok(Vec::new())
+    }
+
+    fn list_devices(&self) -> Result<Vec<DeviceDetails>, AppError> {
+        Ok(Vec::new())
+    }
+
+    fn set_volume(&self, _pid: u32, uid: &str, _volume: f32) -> Result<VolumeChangedPayload, AppError> {
+        Err(AppError::SessionNotFound { uid: uid.to_string() })
+    }
+
+    fn set_mute(&self, _pid: u32, uid: &str, _mute: bool) -> Result<VolumeChangedPayload, AppError> {
+        Err(AppError::SessionNotFound { uid: uid.to_string() })
+    }
+
+    fn get_channel_volumes(&self, _pid: u32, uid: &str) -> Result<Vec<f32>, AppError> {
+        Err(AppError::SessionNotFound { uid: uid.to_string() })
+    }
+
+    fn set_channel_volumes(&self, _pid: u32, uid: &str, _volumes: &[f32]) -> Result<(), AppError> {
+        Err(AppError::SessionNotFound { uid: uid.to_string() })
+    }
+
+    fn get_master_volume(&self, device_id: &str) -> Result<MasterVolume, AppError> {
+        Err(AppError::DeviceUnavailable { device_id: device_id.to_string() })
+    }
+
+    fn set_master_volume(&self, device_id: &str, _volume: f32) -> Result<(), AppError> {
+        Err(AppError::DeviceUnavailable { device_id: device_id.to_string() })
+    }
+
+    fn set_master_mute(&self, device_id: &str, _mute: bool) -> Result<(), AppError> {
+        Err(AppError::DeviceUnavailable { device_id: device_id.to_string() })
+    }
+
+    fn icon_source(&self, _pid: u32, uid: &str) -> Result<IconSource, AppError> {
+        Err(AppError::SessionNotFound { uid: uid.to_string() })
+    }
+
+    fn read_levels(&self) -> Result<AudioLevels, AppError> {
+        // no sessions and no devices, so nothing is ever audible
+        Ok(AudioLevels::default())
+    }
+
+    fn subscribe(&self, _on_event: SessionEventHandler) -> Result<Subscription, AppError> {
+        // nothing will ever happen so there is nothing to cancel either
+        Ok(Subscription::new(|| {}))
+    }
+}
@@ -0,0 +1,168 @@
+// Audio endpoints, render (output devices) and capture (microphones): listing them and the IMMNotificationClient
+// that tells the COM worker when one is added, removed, enabled/disabled or becomes the default.
+use std::sync::mpsc::Sender;
+use windows::{
+    core::{implement, Interface, Result, HSTRING, PCWSTR},
+    Win32::{Devices::FunctionDiscovery::PKEY_Device_FriendlyName, Foundation::{E_FAIL, PROPERTYKEY}, Media::Audio::{
+        eAll, eCapture, eCommunications, eConsole, eMultimedia, eRender, EDataFlow, ERole,
+        IMMDevice, IMMDeviceEnumerator, IMMEndpoint, IMMNotificationClient, IMMNotificationClient_Impl,
+        DEVICE_STATE, DEVICE_STATE_ACTIVE, DEVICE_STATE_DISABLED, DEVICE_STATE_NOTPRESENT, DEVICE_STATE_UNPLUGGED, // endpoint states
+     }, System::Com::STGM_READ,}
+};
+use super::{take_pwstr_to_string, MonitorThreadMessage};
+use crate::audio_backend::{DataFlow, DeviceDetails, DeviceRole, DeviceState};
+
+pub(super) fn get_device_id(device: &IMMDevice) -> Result<String> {
+    unsafe { take_pwstr_to_string(device.GetId()?) }
+        .ok_or_else(|| windows::core::Error::new(E_FAIL, "Device has no id."))
+}
+
+// the name shown in the Windows sound settings, e.g. "Speakers (Realtek High Definition Audio)"
+fn get_device_friendly_name(device: &IMMDevice) -> Result<String> {
+    let property_store = unsafe { device.OpenPropertyStore(STGM_READ)? };
+    let name = unsafe { property_store.GetValue(&PKEY_Device_FriendlyName)? };
+    Ok(name.to_string())
+}
+
+#[allow(non_upper_case_globals)]
+fn device_state(state: DEVICE_STATE) -> DeviceState {
+    match state {
+        DEVICE_STATE_ACTIVE => DeviceState::Active,
+        DEVICE_STATE_DISABLED => DeviceState::Disabled,
+        DEVICE_STATE_NOTPRESENT => DeviceState::NotPresent,
+        DEVICE_STATE_UNPLUGGED => DeviceState::Unplugged,
+        _ => DeviceState::NotPresent,
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn device_role(role: ERole) -> Option<DeviceRole> {
+    match role {
+        eConsole => Some(DeviceRole::Console),
+        eMultimedia => Some(DeviceRole::Multimedia),
+        eCommunications => Some(DeviceRole::Communications),
+        _ => None,
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn data_flow(flow: EDataFlow) -> Option<DataFlow> {
+    match flow {
+        eRender => Some(DataFlow::Render),
+        eCapture => Some(DataFlow::Capture),
+        _ => None,
+    }
+}
+
+fn edata_flow(data_flow: DataFlow) -> EDataFlow {
+    match data_flow {
+        DataFlow::Render => eRender,
+        DataFlow::Capture => eCapture,
+    }
+}
+
+// every endpoint is either a render or a capture one, only eAll (never returned here) is neither
+pub(super) fn get_device_data_flow(device: &IMMDevice) -> Result<DataFlow> {
+    let flow = unsafe { device.cast::<IMMEndpoint>()?.GetDataFlow()? };
+    data_flow(flow).ok_or_else(|| windows::core::Error::new(E_FAIL, "Endpoint has no data flow."))
+}
+
+fn default_device_id(device_enumerator: &IMMDeviceEnumerator, data_flow: DataFlow, role: ERole) -> Option<String> {
+    // fails when there is no device of that direction at all, then no device is the default
+    let device = unsafe { device_enumerator.GetDefaultAudioEndpoint(edata_flow(data_flow), role) }.ok()?;
+    get_device_id(&device).ok()
+}
+
+// the active output and input devices
+pub(super) fn active_devices(device_enumerator: &IMMDeviceEnumerator) -> Result<Vec<IMMDevice>> {
+    let collection = unsafe { device_enumerator.EnumAudioEndpoints(eAll, DEVICE_STATE_ACTIVE)? };
+    let count = unsafe { collection.GetCount()? };
+    (0..count).map(|i| unsafe { collection.Item(i) }).collect()
+}
+
+pub(super) fn get_device_details(device_enumerator: &IMMDeviceEnumerator, device: &IMMDevice) -> Result<DeviceDetails> {
+    let id = get_device_id(device)?;
+    let data_flow = get_device_data_flow(device)?;
+    let is_default = |role| default_device_id(device_enumerator, data_flow, role).as_deref() == Some(id.as_str());
+    Ok(DeviceDetails {
+        name: get_device_friendly_name(device).unwrap_or_else(|_| "Unknown device".to_string()),
+        state: device_state(unsafe { device.GetState()? }),
+        data_flow,
+        is_default_console: is_default(eConsole),
+        is_default_multimedia: is_default(eMultimedia),
+        is_default_communications: is_default(eCommunications),
+        id,
+    })
+}
+
+pub(super) fn list_active_devices(device_enumerator: &IMMDeviceEnumerator) -> Result<Vec<DeviceDetails>> {
+    active_devices(device_enumerator)?
+        .iter()
+        .map(|device| get_device_details(device_enumerator, device))
+        .collect()
+}
+
+// the device behind an id from a notification, if it can play (or record) right now
+pub(super) fn find_active_device(device_enumerator: &IMMDeviceEnumerator, device_id: &str) -> Option<IMMDevice> {
+    let device = unsafe { device_enumerator.GetDevice(&HSTRING::from(device_id)) }.ok()?;
+    let state = unsafe { device.GetState() }.ok()?;
+    (state == DEVICE_STATE_ACTIVE).then_some(device)
+}
+
+fn pcwstr_to_string(pcwstr: &PCWSTR) -> Option<String> {
+    if pcwstr.is_null() {
+        return None;
+    }
+    unsafe { pcwstr.to_string() }.ok().filter(|s| !s.is_empty())
+}
+
+// Windows calls this on its own threads, every notification is only forwarded to the COM worker
+// which re-checks the device itself.
+#[implement(IMMNotificationClient)]
+pub(super) struct DeviceNotificationClient {
+    pub(super) monitor_loop_sender: Sender<MonitorThreadMessage>,
+}
+
+impl DeviceNotificationClient {
+    fn forward(&self, message: MonitorThreadMessage) {
+        if self.monitor_loop_sender.send(message).is_err() {
+            eprintln!("[AudioMonitor] DeviceNotificationClient: Failed to send device change to main monitor loop.");
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+impl IMMNotificationClient_Impl for DeviceNotificationClient_Impl {
+    fn OnDeviceStateChanged(&self, device_id: &PCWSTR, _new_state: DEVICE_STATE) -> Result<()> {
+        if let Some(device_id) = pcwstr_to_string(device_id) {
+            self.forward(MonitorThreadMessage::DeviceChanged(device_id));
+        }
+        Ok(())
+    }
+
+    fn OnDeviceAdded(&self, device_id: &PCWSTR) -> Result<()> {
+        if let Some(device_id) = pcwstr_to_string(device_id) {
+            self.forward(MonitorThreadMessage::DeviceChanged(device_id));
+        }
+        Ok(())
+    }
+
+    fn OnDeviceRemoved(&self, device_id: &PCWSTR) -> Result<()> {
+        if let Some(device_id) = pcwstr_to_string(device_id) {
+            self.forward(MonitorThreadMessage::DeviceChanged(device_id));
+        }
+        Ok(())
+    }
+
+    fn OnDefaultDeviceChanged(&self, flow: EDataFlow, role: ERole, default_device_id: &PCWSTR) -> Result<()> {
+        if let (Some(data_flow), Some(role)) = (data_flow(flow), device_role(role)) {
+            // a null id means there is no device of that direction left for this role
+            self.forward(MonitorThreadMessage::DefaultDeviceChanged { data_flow, role, device_id: pcwstr_to_string(default_device_id) });
+        }
+        Ok(())
+    }
+
+    fn OnPropertyValueChanged(&self, _device_id: &PCWSTR, _key: &PROPERTYKEY) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+// The master volume of an endpoint (IAudioEndpointVolume), the one the Windows volume flyout changes for outputs
+// and the microphone level for inputs, and the callback Windows calls when it changes, from our app or from anywhere else.
+use windows::{
+    core::{implement, Result},
+    Win32::Media::Audio::{Endpoints::{IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioEndpointVolumeCallback_Impl}, AUDIO_VOLUME_NOTIFICATION_DATA},
+};
+use super::EventDispatcher;
+use crate::audio_backend::{MasterVolume, SessionEvent};
+
+pub(super) fn get_master_volume(endpoint_volume: &IAudioEndpointVolume, device_id: &str) -> Result<MasterVolume> {
+    let volume = unsafe { endpoint_volume.GetMasterVolumeLevelScalar()? };
+    let muted = unsafe { endpoint_volume.GetMute()? };
+    Ok(MasterVolume { device_id: device_id.to_string(), volume, is_muted: muted.as_bool() })
+}
+
+pub(super) fn set_master_volume(endpoint_volume: &IAudioEndpointVolume, volume: f32) -> Result<()> {
+    // the scalar level is the same 0..1 curve the flyout slider uses, not decibels
+    unsafe { endpoint_volume.SetMasterVolumeLevelScalar(volume.clamp(0.0, 1.0), std::ptr::null()) }
+}
+
+// like the session mute, only call SetMute for a real change so no useless notification goes out
+pub(super) fn set_master_mute(endpoint_volume: &IAudioEndpointVolume, mute: bool) -> Result<()> {
+    let is_mute = unsafe { endpoint_volume.GetMute()? };
+    if is_mute.as_bool() != mute {
+        unsafe { endpoint_volume.SetMute(mute, std::ptr::null())? };
+    }
+    Ok(())
+}
+
+// one per watched device, it only carries the new level so it dispatches straight from the Windows thread
+#[implement(IAudioEndpointVolumeCallback)]
+pub(super) struct EndpointVolumeListener {
+    pub(super) device_id: String,
+    pub(super) events: EventDispatcher,
+}
+
+#[allow(non_snake_case)]
+impl IAudioEndpointVolumeCallback_Impl for EndpointVolumeListener_Impl {
+    fn OnNotify(&self, notify_data: *mut AUDIO_VOLUME_NOTIFICATION_DATA) -> Result<()> {
+        // the data is only valid for the duration of the call, we copy what we need out of it
+        if let Some(data) = unsafe { notify_data.as_ref() } {
+            self.events.dispatch(SessionEvent::MasterVolumeChanged(MasterVolume {
+                device_id: self.device_id.clone(),
+                volume: data.fMasterVolume,
+                is_muted: data.bMuted.as_bool(),
+            }));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,25 @@
+// Peak meters (IAudioMeterInformation). Every registered session and watched endpoint keeps one,
+// the level meter asks the worker to read them all at once for each "audio-levels" batch.
+use windows::Win32::Media::Audio::Endpoints::IAudioMeterInformation;
+use super::{SessionRegistry, WatchedDevice};
+use crate::audio_backend::{AudioLevels, DeviceLevel, SessionLevel};
+use std::collections::HashMap;
+
+// the peak of the last metering period (about 10ms), already scaled to 0..1 and after the session volume
+fn read_peak(meter: &IAudioMeterInformation) -> Option<f32> {
+    unsafe { meter.GetPeakValue() }.ok()
+}
+
+// a meter that fails to read (the device went away mid tick) is just left out, the next tick will know better
+pub(super) fn read_levels(registry: &SessionRegistry, watched_devices: &HashMap<String, WatchedDevice>) -> AudioLevels {
+    AudioLevels {
+        sessions: registry
+            .iter()
+            .filter_map(|(uid, session)| read_peak(&session.meter).map(|peak| SessionLevel { session_uid: uid.clone(), peak }))
+            .collect(),
+        devices: watched_devices
+            .iter()
+            .filter_map(|(device_id, device)| read_peak(&device.meter).map(|peak| DeviceLevel { device_id: device_id.clone(), peak }))
+            .collect(),
+    }
+}
@@ -0,0 +1,970 @@
+// WASAPI (Windows Core Audio) implementation of the AudioBackend trait.
+// All the COM calls of the app live in this module, the endpoints (output and input devices) in `devices.rs`.
+// Playback sessions of render endpoints and recording sessions of capture endpoints go through the same listeners.
+use std::{
+    collections::{HashMap, VecDeque}, sync::{
+        atomic::{AtomicU64, Ordering as AtomicOrdering}, mpsc::{self, Receiver, Sender}, Arc, Mutex
+    }, thread
+};
+use windows::{
+    core::{implement, Interface, Ref, Result, BOOL, GUID, PCWSTR, PWSTR}, // Core Windows interface types
+    Win32::{Foundation::{E_FAIL, S_OK}, Media::Audio::{
+        AudioSessionDisconnectReason, AudioSessionState, AudioSessionStateActive, AudioSessionStateExpired, AudioSessionStateInactive,
+        DisconnectReasonDeviceRemoval, DisconnectReasonExclusiveModeOverride, DisconnectReasonFormatChanged, DisconnectReasonServerShutdown,
+        DisconnectReasonSessionDisconnected, DisconnectReasonSessionLogoff,
+        IAudioSessionControl, IAudioSessionControl2, IChannelAudioVolume, IAudioSessionEnumerator, IAudioSessionEvents,
+        IAudioSessionEvents_Impl, IAudioSessionManager2, IAudioSessionNotification, IAudioSessionNotification_Impl,
+        IMMDevice, IMMDeviceEnumerator, IMMNotificationClient, ISimpleAudioVolume, MMDeviceEnumerator, // audio session states
+        Endpoints::{IAudioEndpointVolume, IAudioEndpointVolumeCallback, IAudioMeterInformation},
+     }, System::{Com::{
+        CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_ALL, COINIT_MULTITHREADED // COM threading model (multithreaded apartment)
+     },},}
+};
+use crate::app_groups;
+use crate::error::AppError;
+use crate::process_info;
+use super::session_id::{SessionIdentifier, SYSTEM_SOUNDS_APP_KEY};
+use super::{AudioBackend, AudioLevels, ChannelVolumesPayload, DataFlow, DefaultDeviceChangedPayload, DeviceDetails, DeviceRole, IconSource, MasterVolume, SessionClosedPayload, SessionCloseReason, SessionDetails, SessionEvent, SessionEventHandler, SessionGroupingPayload, SessionIconPayload, SessionRenamedPayload, SessionState, SessionStatePayload, Subscription, VolumeChangedPayload, SYSTEM_SOUNDS_NAME, system_sounds_uid};
+
+mod devices;
+use devices::{active_devices, find_active_device, get_device_data_flow, get_device_details, get_device_id, list_active_devices, DeviceNotificationClient};
+mod endpoint_volume;
+use endpoint_volume::{get_master_volume, set_master_mute, set_master_volume, EndpointVolumeListener};
+mod meters;
+
+// same as below for the strings Windows passes to the callbacks, they belong to the caller so nothing is freed
+unsafe fn pcwstr_to_string(pcwstr: &PCWSTR) -> Option<String> {
+    if pcwstr.is_null() {
+        return None;
+    }
+    pcwstr.to_string().ok().filter(|s| !s.is_empty())
+}
+
+unsafe fn take_pwstr_to_string(pwstr: PWSTR) -> Option<String> {
+
+    if pwstr.is_null(){
+        return None; // if pwstr is null we return None
+    }    
+
+    let owned_string = pwstr.to_string() // convert to string
+    .ok()
+    .filter(|s| !s.is_empty()); // check if the string is empty and returns None if it is
+
+    CoTaskMemFree(Some(pwstr.as_ptr() as _)); // <- Frees the memory here
+    
+    owned_string
+     
+}
+
+// a std channel and not tokio's oneshot: its blocking_recv panics inside a runtime, this one only blocks
+type Reply<T> = Sender<std::result::Result<T, AppError>>;
+
+// Everything the COM worker thread does goes through this channel: the listeners report new and
+// closed sessions, the backend sends the commands and gets the answer back on a channel of its own.
+// One thread owns the apartment and the sessions, so commands run one after the other in the order they were sent.
+enum MonitorThreadMessage {
+    SessionCreated { device_id: String, data_flow: DataFlow, control: IAudioSessionControl },
+    ExistingSessionClosed(String, SessionCloseReason),
+    DeviceChanged(String), // an endpoint was added, removed or changed state, carries its id
+    DefaultDeviceChanged { data_flow: DataFlow, role: DeviceRole, device_id: Option<String> },
+    ListSessions(Reply<Vec<SessionDetails>>),
+    ListDevices(Reply<Vec<DeviceDetails>>),
+    SetVolume { pid: u32, uid: String, volume: f32, reply: Reply<VolumeChangedPayload> },
+    SetMute { pid: u32, uid: String, mute: bool, reply: Reply<VolumeChangedPayload> },
+    GetChannelVolumes { pid: u32, uid: String, reply: Reply<Vec<f32>> },
+    SetChannelVolumes { pid: u32, uid: String, volumes: Vec<f32>, reply: Reply<()> },
+    GetMasterVolume { device_id: String, reply: Reply<MasterVolume> },
+    SetMasterVolume { device_id: String, volume: f32, reply: Reply<()> },
+    SetMasterMute { device_id: String, mute: bool, reply: Reply<()> },
+    GetIconSource { pid: u32, uid: String, reply: Reply<IconSource> },
+    ReadLevels(Reply<AudioLevels>),
+    Shutdown, // sent by AudioBackend::shutdown on exit, or when the backend is dropped without it
+}
+
+// The only COM object in here is the IAudioSessionControl of SessionCreated. Audio session objects are
+// free-threaded (they aggregate the free-threaded marshaler) so it can be handed from the notification
+// thread to the worker thread.
+unsafe impl Send for MonitorThreadMessage {}
+
+// One session the worker is listening to. Its ISimpleAudioVolume and IChannelAudioVolume are kept here so the
+// commands can change the volume straight away instead of enumerating every session again.
+struct RegisteredSession {
+    process_id: u32,
+    device_id: String,
+    data_flow: DataFlow,
+    control: IAudioSessionControl, // Needed to read the details and to unregister the listener.
+    volume: ISimpleAudioVolume,
+    channels: IChannelAudioVolume,
+    meter: IAudioMeterInformation,
+    listener: IAudioSessionEvents, // kept alive for as long as the session is registered
+}
+
+// Key: the session's unique instance identifier (`session_uid`).
+// Only the worker thread touches it, it adds sessions when they are created and removes them when they close.
+type SessionRegistry = HashMap<String, RegisteredSession>;
+
+// An endpoint the worker listens to for new sessions and master volume changes.
+// Kept so the notifiers stay alive and can be unregistered at shutdown.
+struct WatchedDevice {
+    session_manager: IAudioSessionManager2,
+    notifier: IAudioSessionNotification,
+    endpoint_volume: IAudioEndpointVolume, // also what the master volume commands go through
+    volume_listener: IAudioEndpointVolumeCallback,
+    meter: IAudioMeterInformation, // the peak of everything playing on (or recorded from) the endpoint
+}
+
+impl WatchedDevice {
+    fn unregister(&self) {
+        unsafe {
+            let _ = self.session_manager.UnregisterSessionNotification(&self.notifier);
+            let _ = self.endpoint_volume.UnregisterControlChangeNotify(&self.volume_listener);
+        }
+    }
+}
+
+// The subscribers of the backend. The watcher runs for as long as the backend exists,
+// subscribing only adds a handler here and dropping the Subscription removes it.
+#[derive(Clone, Default)]
+struct EventDispatcher {
+    handlers: Arc<Mutex<HashMap<u64, SessionEventHandler>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl EventDispatcher {
+    fn add(&self, handler: SessionEventHandler) -> u64 {
+        let id = self.next_id.fetch_add(1, AtomicOrdering::Relaxed);
+        self.handlers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(id, handler);
+        id
+    }
+
+    fn remove(&self, id: u64) {
+        self.handlers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).remove(&id);
+    }
+
+    // handlers are called outside the lock so a slow handler can't block a subscribe/unsubscribe
+    fn dispatch(&self, event: SessionEvent) {
+        let handlers: Vec<SessionEventHandler> = self.handlers.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).values().cloned().collect();
+        for handler in handlers {
+            handler(event.clone());
+        }
+    }
+}
+
+
+
+
+#[implement(IAudioSessionEvents)]
+struct IndividualSessionEventsListener {
+    session_uid: String,
+    details: SessionDetails, // as it was registered, what the new group key is built from when the grouping changes
+    events: EventDispatcher,
+    monitor_loop_sender: Sender<MonitorThreadMessage>,
+}
+
+#[allow(non_snake_case)]
+impl IAudioSessionEvents_Impl for IndividualSessionEventsListener_Impl {
+    // the registry reads the name again on every listing, so the event is all that is left to do
+    fn OnDisplayNameChanged(&self, new_display_name: &PCWSTR, _event_context: *const GUID) -> Result<()> {
+        let display_name = unsafe { pcwstr_to_string(new_display_name) };
+        self.events.dispatch(SessionEvent::Renamed(SessionRenamedPayload { session_uid: self.session_uid.clone(), display_name }));
+        Ok(())
+    }
+
+    fn OnIconPathChanged(&self, new_icon_path: &PCWSTR, _event_context: *const GUID) -> Result<()> {
+        let icon_path = unsafe { pcwstr_to_string(new_icon_path) };
+        self.events.dispatch(SessionEvent::IconChanged(SessionIconPayload { session_uid: self.session_uid.clone(), icon_path }));
+        Ok(())
+    }
+
+    // the array holds every channel, not only `changed_channel`, so the UI always gets the full set
+    fn OnChannelVolumeChanged(&self, channel_count: u32, new_channel_volume_array: *const f32, _changed_channel: u32, _event_context: *const GUID) -> Result<()> {
+        if new_channel_volume_array.is_null() {
+            return Ok(());
+        }
+        // only valid for the duration of the call, so it is copied out
+        let volumes = unsafe { std::slice::from_raw_parts(new_channel_volume_array, channel_count as usize) }.to_vec();
+        self.events.dispatch(SessionEvent::ChannelsChanged(ChannelVolumesPayload { session_uid: self.session_uid.clone(), volumes }));
+        Ok(())
+    }
+
+    fn OnGroupingParamChanged(&self, new_grouping_param: *const GUID, _event_context: *const GUID) -> Result<()> {
+        if let Some(grouping_param) = unsafe { new_grouping_param.as_ref() } {
+            let grouping_id = grouping_id(grouping_param);
+            let group_key = app_groups::regrouped_key(&self.details, grouping_id.as_deref());
+            self.events.dispatch(SessionEvent::GroupingChanged(SessionGroupingPayload { session_uid: self.session_uid.clone(), grouping_id, group_key }));
+        }
+        Ok(())
+    }
+
+    
+    fn OnSimpleVolumeChanged(&self, new_volume: f32, new_mute: BOOL, _event_context: *const GUID) -> Result<()> {
+        let payload = VolumeChangedPayload {
+            session_uid: self.session_uid.clone(),
+            volume: new_volume,
+            is_muted: new_mute.as_bool(), 
+        };
+        self.events.dispatch(SessionEvent::VolumeChanged(payload));
+
+        Ok(()) 
+    }
+    fn OnStateChanged(&self, new_state: AudioSessionState ) -> Result<()> {
+
+        match session_state(new_state) {
+            Some(SessionState::Expired) => {
+                // the worker reports the close once it has removed the session, so a session that expires
+                // and gets disconnected (or goes away with its device) is only reported once, with the first reason
+                if self.monitor_loop_sender.send(MonitorThreadMessage::ExistingSessionClosed(self.session_uid.clone(), SessionCloseReason::Expired)).is_err() {
+                    eprintln!("[AudioMonitor] Failed to send session closed message for {} to main loop.", self.session_uid);
+                }
+            }
+            Some(state) => {
+                let payload = SessionStatePayload {
+                    session_uid: self.session_uid.clone(),
+                    state,
+                };
+                self.events.dispatch(SessionEvent::StateChanged(payload));
+            }
+            None => {
+                eprintln!("[AudioMonitor] Session '{}' received an unknown state: {:?}", self.session_uid, new_state.0);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn OnSessionDisconnected(&self, disconnect_reason: AudioSessionDisconnectReason) -> Result<()> {
+        let reason = close_reason(disconnect_reason);
+        if self.monitor_loop_sender.send(MonitorThreadMessage::ExistingSessionClosed(self.session_uid.clone(), reason)).is_err() {
+            eprintln!("[AudioMonitor] Failed to send session closed message for {} to main loop.", self.session_uid);
+        }
+
+        Ok(())
+    }
+}
+
+
+#[implement(IAudioSessionNotification)]
+struct GlobalAudioSessionNotifier  {
+    device_id: String, // one notifier per endpoint, so new sessions know which device they play to (or record from)
+    data_flow: DataFlow,
+    monitor_loop_sender: Sender<MonitorThreadMessage>,
+}
+#[allow(non_snake_case)]
+impl IAudioSessionNotification_Impl for GlobalAudioSessionNotifier_Impl  { 
+    // --- THIS IS THE KEY METHOD called by Windows when a NEW session is created ---
+    // This callback runs on a Windows thread. Send the new session to the main loop for processing.
+    fn OnSessionCreated(&self, new_session_control: Ref<'_, IAudioSessionControl>) -> Result<()> { 
+        // here for whatever reason new_session_control goes from type Ref<'_, IAudioSessionControl> to Option<IAudioSessionControl> hence why we handle Some()
+        if let Some(owned_session_control)  = new_session_control.clone() {
+            let message = MonitorThreadMessage::SessionCreated { device_id: self.device_id.clone(), data_flow: self.data_flow, control: owned_session_control };
+            if self.monitor_loop_sender.send(message).is_err(){
+                eprintln!("[AudioMonitor] GlobalNotifier: Failed to send new session to main monitor loop.");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn session_state(state: AudioSessionState) -> Option<SessionState> {
+    match state {
+        AudioSessionStateActive => Some(SessionState::Active),
+        AudioSessionStateInactive => Some(SessionState::Inactive),
+        AudioSessionStateExpired => Some(SessionState::Expired),
+        _ => None,
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn close_reason(reason: AudioSessionDisconnectReason) -> SessionCloseReason {
+    match reason {
+        DisconnectReasonDeviceRemoval => SessionCloseReason::DeviceRemoval,
+        DisconnectReasonServerShutdown => SessionCloseReason::ServerShutdown,
+        DisconnectReasonFormatChanged => SessionCloseReason::FormatChanged,
+        DisconnectReasonSessionLogoff => SessionCloseReason::SessionLogoff,
+        DisconnectReasonSessionDisconnected => SessionCloseReason::SessionDisconnected,
+        DisconnectReasonExclusiveModeOverride => SessionCloseReason::ExclusiveModeOverride,
+        _ => {
+            eprintln!("[AudioMonitor] Unknown session disconnect reason: {:?}", reason.0);
+            SessionCloseReason::Expired
+        }
+    }
+}
+
+// GUID_NULL means the app never set one
+fn grouping_id(grouping_param: &GUID) -> Option<String> {
+    (*grouping_param != GUID::zeroed()).then(|| format!("{:?}", grouping_param))
+}
+
+fn get_session_details(session_control: &IAudioSessionControl, device_id: &str, data_flow: DataFlow) -> Result<SessionDetails> {
+    let session_volume_control: ISimpleAudioVolume = session_control.cast()?;
+    let volume = unsafe {
+        session_volume_control.GetMasterVolume()?
+    };
+    let muted = unsafe {
+        session_volume_control.GetMute()?
+    };
+
+    // an unknown state is treated like a silent session
+    let state = session_state(unsafe { session_control.GetState()? }).unwrap_or(SessionState::Inactive);
+
+
+    let session_control2: IAudioSessionControl2 = session_control.cast()?;
+    let id = unsafe {
+        session_control2.GetProcessId().unwrap_or(0)
+    };
+
+    // S_OK for the system sounds session, S_FALSE for every other one
+    let is_system_sounds = unsafe { session_control2.IsSystemSoundsSession() } == S_OK;
+
+    let (uid, app_key) = if is_system_sounds {
+        (system_sounds_uid(device_id), Some(SYSTEM_SOUNDS_APP_KEY.to_string()))
+    } else {
+        let pwstr = unsafe { session_control2.GetSessionInstanceIdentifier()? };
+        match unsafe { take_pwstr_to_string(pwstr) } { // this converts and frees the pwstr
+            Some(uid) => {
+                let app_key = SessionIdentifier::parse(&uid).map(|identifier| identifier.app_key());
+                (uid, app_key)
+            }
+            None => {
+                eprintln!("[AudioMonitor] Could not get a valid UID for a session. Skipping it.");
+                return Err(windows::core::Error::new(E_FAIL, "Session has no instance identifier."));
+            }
+        }
+    };
+
+    // the three are kept apart, the UI decides what to show when the app didn't name its session
+    // the system sounds session is named with a resource string ("@%SystemRoot%\System32\AudioSrv.Dll,-202")
+    let display_name = if is_system_sounds {
+        Some(SYSTEM_SOUNDS_NAME.to_string())
+    } else {
+        unsafe { take_pwstr_to_string(session_control2.GetDisplayName()?) }
+    };
+    let icon_path = unsafe {
+        let pwstr = session_control2.GetIconPath()?;
+        take_pwstr_to_string(pwstr)
+    };
+    let grouping_id = grouping_id(&unsafe { session_control2.GetGroupingParam()? });
+    // the system sounds session isn't owned by a process, its pid is 0
+    let identity = if !is_system_sounds && id != 0 { process_info::process_identity(id) } else { None };
+    let exe_name = identity.as_ref().map(|identity| identity.process.exe_name.clone());
+    let app = identity.map(|identity| identity.app);
+
+    let mut details = SessionDetails {
+        process_id: id,
+        session_uid: uid,
+        display_name,
+        exe_name,
+        icon_path,
+        grouping_id,
+        group_key: String::new(),
+        app,
+        app_key,
+        is_system_sounds,
+        session_volume: volume,
+        is_muted: muted.as_bool(),
+        state,
+        device_id: device_id.to_string(),
+        data_flow,
+    };
+    details.group_key = app_groups::group_key(&details);
+    Ok(details)
+}
+
+// registers a listener on the session and keeps its handles in the registry
+// returns false if the session could not be registered, it is then skipped like before
+fn register_session(
+    session_control: IAudioSessionControl,
+    details: &SessionDetails,
+    events: &EventDispatcher,
+    monitor_loop_sender: &Sender<MonitorThreadMessage>,
+    registry: &mut SessionRegistry,
+) -> bool {
+    let volume: ISimpleAudioVolume = match session_control.cast() {
+        Ok(volume) => volume,
+        Err(e) => {
+            eprintln!("[AudioMonitor] Failed to cast ISimpleAudioVolume on session {}: {:?}", details.session_uid, e);
+            return false;
+        }
+    };
+
+    let channels: IChannelAudioVolume = match session_control.cast() {
+        Ok(channels) => channels,
+        Err(e) => {
+            eprintln!("[AudioMonitor] Failed to cast IChannelAudioVolume on session {}: {:?}", details.session_uid, e);
+            return false;
+        }
+    };
+
+    let meter: IAudioMeterInformation = match session_control.cast() {
+        Ok(meter) => meter,
+        Err(e) => {
+            eprintln!("[AudioMonitor] Failed to cast IAudioMeterInformation on session {}: {:?}", details.session_uid, e);
+            return false;
+        }
+    };
+
+    // create an instance of the struct and convert it to a com object using .into()
+    let listener: IAudioSessionEvents = IndividualSessionEventsListener{
+        session_uid: details.session_uid.clone(),
+        details: details.clone(),
+        events: events.clone(),
+        monitor_loop_sender: monitor_loop_sender.clone(),
+    }.into();
+
+    // register this com object with RegisterAudioSessionNotification so that windows knows that it exists 
+    if unsafe { session_control.RegisterAudioSessionNotification(&listener).is_err() } {
+        return false;
+    }
+
+    // store it in the registry so that it keeps it alive because listener will go out of scope at the end of this function
+    registry.insert(details.session_uid.clone(), RegisteredSession {
+        process_id: details.process_id,
+        device_id: details.device_id.clone(),
+        data_flow: details.data_flow,
+        control: session_control,
+        volume,
+        channels,
+        meter,
+        listener,
+    });
+    true
+}
+
+// reads the current state of every registered session, no enumeration needed
+fn list_registered_sessions(registry: &SessionRegistry) -> Vec<SessionDetails> {
+    let mut sessions_data = Vec::with_capacity(registry.len());
+    for (uid, session) in registry.iter() {
+        match get_session_details(&session.control, &session.device_id, session.data_flow) {
+            Ok(details) => sessions_data.push(details),
+            Err(e) => eprintln!("[AudioMonitor] Failed to get details of session {}: {:?}", uid, e),
+        }
+    }
+    sessions_data
+}
+
+// the pid has to match so a reused uid can't hit another app, the system sounds session is found with pid 0
+fn find_session<'a>(registry: &'a SessionRegistry, pid: u32, uid: &str) -> Option<&'a RegisteredSession> {
+    registry.get(uid).filter(|s| s.process_id == pid)
+}
+
+fn set_session_volume(registry: &SessionRegistry, pid: u32, uid: &str, volume: f32) -> std::result::Result<VolumeChangedPayload, AppError> {
+    let session = find_session(registry, pid, uid).ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+    unsafe {
+        session.volume.SetMasterVolume(
+            volume, // the app volume is supposed to be a percentage of the master volume so windows will multiplly this to the master volume of windows
+            std::ptr::null()
+        ).map_err(|e| AppError::com(format!("Failed to SetMasterVolume on {}", uid), &e))?;
+    }
+    read_session_volume(session, uid)
+}
+
+// the volume and mute state the session has now, what the set commands answer with
+fn read_session_volume(session: &RegisteredSession, uid: &str) -> std::result::Result<VolumeChangedPayload, AppError> {
+    unsafe {
+        let volume = session.volume.GetMasterVolume().map_err(|e| AppError::com(format!("Failed to GetMasterVolume on {}", uid), &e))?;
+        let is_muted = session.volume.GetMute().map_err(|e| AppError::com(format!("Failed to GetMute on {}", uid), &e))?;
+        Ok(VolumeChangedPayload { session_uid: uid.to_string(), volume, is_muted: is_muted.as_bool() })
+    }
+}
+
+fn get_icon_source(registry: &SessionRegistry, pid: u32, uid: &str) -> std::result::Result<IconSource, AppError> {
+    let session = find_session(registry, pid, uid).ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+    let icon_path = unsafe {
+        let pwstr = session.control.GetIconPath().map_err(|e| AppError::com(format!("Failed to GetIconPath on {}", uid), &e))?;
+        take_pwstr_to_string(pwstr)
+    };
+    Ok(IconSource { exe_path: process_info::image_path(pid), icon_path })
+}
+
+fn set_session_mute(registry: &SessionRegistry, pid: u32, uid: &str, mute: bool) -> std::result::Result<VolumeChangedPayload, AppError> {
+    let session = find_session(registry, pid, uid).ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+    unsafe {
+        let is_mute = session.volume.GetMute().map_err(|e| AppError::com(format!("Failed to GetMute on {}", uid), &e))?;
+        if is_mute.as_bool() != mute {
+            session.volume.SetMute(
+                mute, 
+                std::ptr::null()
+            ).map_err(|e| AppError::com(format!("Failed to SetMute on {}", uid), &e))?;
+        }
+    }
+    read_session_volume(session, uid)
+}
+
+fn get_channel_volumes(registry: &SessionRegistry, pid: u32, uid: &str) -> std::result::Result<Vec<f32>, AppError> {
+    let session = find_session(registry, pid, uid).ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+    unsafe {
+        let count = session.channels.GetChannelCount().map_err(|e| AppError::com(format!("Failed to GetChannelCount on {}", uid), &e))?;
+        let mut volumes = vec![0.0; count as usize];
+        session.channels.GetAllVolumes(&mut volumes).map_err(|e| AppError::com(format!("Failed to GetAllVolumes on {}", uid), &e))?;
+        Ok(volumes)
+    }
+}
+
+fn set_channel_volumes(registry: &SessionRegistry, pid: u32, uid: &str, volumes: &[f32]) -> std::result::Result<(), AppError> {
+    let session = find_session(registry, pid, uid).ok_or_else(|| AppError::SessionNotFound { uid: uid.to_string() })?;
+    unsafe {
+        // SetAllVolumes fails with E_INVALIDARG on a count mismatch, checking first gives a readable error
+        let count = session.channels.GetChannelCount().map_err(|e| AppError::com(format!("Failed to GetChannelCount on {}", uid), &e))?;
+        if volumes.len() != count as usize {
+            return Err(AppError::InvalidArgument(format!("Session {} has {} channels, got {} volumes", uid, count, volumes.len())));
+        }
+        let volumes: Vec<f32> = volumes.iter().map(|volume| volume.clamp(0.0, 1.0)).collect();
+        session.channels.SetAllVolumes(&volumes, std::ptr::null()).map_err(|e| AppError::com(format!("Failed to SetAllVolumes on {}", uid), &e))?;
+    }
+    Ok(())
+}
+
+// Starts listening for new sessions on one endpoint and registers the sessions already playing to (or recording from) it.
+// Those sessions are returned: on a device that was just plugged in they are new to the UI, but the SessionCreated
+// listener never reports them (they were registered before it could), so the caller sends them as created.
+fn watch_device(
+    device: &IMMDevice,
+    events: &EventDispatcher,
+    monitor_loop_sender: &Sender<MonitorThreadMessage>,
+    registry: &mut SessionRegistry,
+) -> Result<(String, WatchedDevice, Vec<SessionDetails>)> {
+    let device_id = get_device_id(device)?;
+    let data_flow = get_device_data_flow(device)?;
+    let session_manager: IAudioSessionManager2 = unsafe { device.Activate(CLSCTX_ALL, None)? };
+    let meter: IAudioMeterInformation = unsafe { device.Activate(CLSCTX_ALL, None)? };
+
+    // We register COM listeners that Windows will call when audio session events happen.
+    // The main loop processes the results from these listeners.
+    // (The COM callbacks are executed on separate, Windows-managed threads.)
+
+    // create an instance of the struct and convert it to a com object using .into()
+    let notifier: IAudioSessionNotification = GlobalAudioSessionNotifier { device_id: device_id.clone(), data_flow, monitor_loop_sender: monitor_loop_sender.clone() }.into();
+    // register this com object with RegisterSessionNotification so that windows knows that it exists 
+    unsafe { session_manager.RegisterSessionNotification(&notifier)? };
+
+    let endpoint_volume: IAudioEndpointVolume = unsafe { device.Activate(CLSCTX_ALL, None)? };
+    let volume_listener: IAudioEndpointVolumeCallback = EndpointVolumeListener { device_id: device_id.clone(), events: events.clone() }.into();
+    if let Err(e) = unsafe { endpoint_volume.RegisterControlChangeNotify(&volume_listener) } {
+        unsafe { let _ = session_manager.UnregisterSessionNotification(&notifier); }
+        return Err(e);
+    }
+
+    let session_enumerator: IAudioSessionEnumerator = unsafe { session_manager.GetSessionEnumerator()? };
+    let session_count = unsafe { session_enumerator.GetCount()? };
+
+    // This is the only full enumeration of the device's sessions, from now on the registry is kept up to date by the listeners.
+    let mut registered = Vec::new();
+    for i in 0..session_count {
+
+        let session_control: IAudioSessionControl = unsafe { 
+            match session_enumerator.GetSession(i) {
+                Ok(i) => i,
+                Err(e) => {
+                    eprintln!("[AudioMonitor] Failed to get session {} of device {}: {:?}", i, device_id, e);
+                    continue // if one session fails we skip it to the next session in the loop
+                }
+            }
+        };
+
+        let details = match get_session_details(&session_control, &device_id, data_flow) {
+            Ok(details) => details,
+            Err(e) => {
+                eprintln!("[AudioMonitor] Failed to get details of session {} of device {}: {:?}", i, device_id, e);
+                continue // if one session fails to get details we again skip to the next
+            }
+        };
+
+        // for each session i now we have registered it with com notifications when a change happens to it it will be detected
+        if register_session(session_control, &details, events, monitor_loop_sender, registry) {
+            registered.push(details);
+        }
+    }
+
+    Ok((device_id, WatchedDevice { session_manager, notifier, endpoint_volume, volume_listener, meter }, registered))
+}
+
+// Stops listening to an endpoint that went away (unplugged, disabled...) and closes its sessions.
+fn unwatch_device(device_id: &str, watched_devices: &mut HashMap<String, WatchedDevice>, registry: &mut SessionRegistry, events: &EventDispatcher) {
+    let Some(device) = watched_devices.remove(device_id) else { return };
+    device.unregister();
+
+    let closed: Vec<String> = registry.iter().filter(|(_, s)| s.device_id == device_id).map(|(uid, _)| uid.clone()).collect();
+    for uid in closed {
+        close_session(&uid, SessionCloseReason::DeviceRemoval, registry, events);
+    }
+    events.dispatch(SessionEvent::DeviceRemoved(device_id.to_string()));
+}
+
+fn close_session(uid: &str, reason: SessionCloseReason, registry: &mut SessionRegistry, events: &EventDispatcher) {
+    if let Some(session) = registry.remove(uid) {
+        unsafe {let _ = session.control.UnregisterAudioSessionNotification(&session.listener);}
+        events.dispatch(SessionEvent::Closed(SessionClosedPayload { session_uid: uid.to_string(), reason }));
+    }
+}
+
+// Dragging a slider sends a SetVolume for every pixel. If a newer volume for the same session is already
+// waiting in the queue there is no point in applying this one, the newer one replaces it.
+fn has_newer_volume(pending: &VecDeque<MonitorThreadMessage>, pid: u32, uid: &str) -> bool {
+    pending.iter().any(|message| matches!(message, MonitorThreadMessage::SetVolume { pid: p, uid: u, .. } if *p == pid && u == uid))
+}
+
+// same thing for the balance and master sliders
+fn has_newer_channel_volumes(pending: &VecDeque<MonitorThreadMessage>, pid: u32, uid: &str) -> bool {
+    pending.iter().any(|message| matches!(message, MonitorThreadMessage::SetChannelVolumes { pid: p, uid: u, .. } if *p == pid && u == uid))
+}
+
+fn has_newer_master_volume(pending: &VecDeque<MonitorThreadMessage>, device_id: &str) -> bool {
+    pending.iter().any(|message| matches!(message, MonitorThreadMessage::SetMasterVolume { device_id: d, .. } if d == device_id))
+}
+
+fn find_watched_device<'a>(watched_devices: &'a HashMap<String, WatchedDevice>, device_id: &str) -> std::result::Result<&'a WatchedDevice, AppError> {
+    watched_devices.get(device_id).ok_or_else(|| AppError::DeviceUnavailable { device_id: device_id.to_string() })
+}
+
+// The COM worker: the only thread that initializes COM and touches the audio sessions.
+// It catches new opened or removed process audio instances and runs the commands sent by the backend,
+// every change is reported through `events`, `ready` tells the backend if the COM setup worked
+fn com_worker_loop(
+    events: EventDispatcher,
+    monitor_loop_sender: Sender<MonitorThreadMessage>,
+    monitor_loop_receiver: Receiver<MonitorThreadMessage>,
+    ready: Sender<std::result::Result<(), AppError>>,
+)  {
+
+    unsafe { 
+        if let Err(e) = CoInitializeEx( None, COINIT_MULTITHREADED ).ok() {
+            let _ = ready.send(Err(AppError::com("COM failed to initialize", &e)));
+            return;
+        }
+    }
+
+    let mut registry = SessionRegistry::new();
+
+    // The endpoints we listen to, keyed by device id. Keeping them here keeps their notifiers
+    // alive for the entire duration of the worker so Windows can call `OnSessionCreated`,
+    // and lets us unregister them again during shutdown.
+    let mut watched_devices: HashMap<String, WatchedDevice> = HashMap::new();
+
+
+    let device_enumerator: IMMDeviceEnumerator = unsafe {
+        match CoCreateInstance(
+            &MMDeviceEnumerator,
+            None,
+            CLSCTX_ALL,) {
+                Ok(d) => d,
+                Err(e) => {
+                    let _ = ready.send(Err(AppError::com("Failed to create the MMDeviceEnumerator instance", &e)));
+                    CoUninitialize();
+                    return
+                }
+            }
+    };
+
+    // Windows tells this client about devices being plugged in, removed or made the default.
+    let device_notifier: IMMNotificationClient = DeviceNotificationClient { monitor_loop_sender: monitor_loop_sender.clone() }.into();
+    if unsafe { device_enumerator.RegisterEndpointNotificationCallback(&device_notifier).is_err() } {
+        eprintln!("[AudioMonitor] Failed to register the device notification client, device changes won't be followed.");
+    }
+
+    // every active output and input device, not only the default ones, so apps playing to headphones
+    // or recording from a USB mic show up too
+    let devices = match active_devices(&device_enumerator) {
+        Ok(devices) => devices,
+        Err(e) => {
+            let _ = ready.send(Err(AppError::com("Failed to enumerate audio devices", &e)));
+            CoUninitialize();
+            return
+        }
+    };
+
+    for device in &devices {
+        match watch_device(device, &events, &monitor_loop_sender, &mut registry) {
+            // nobody is subscribed yet, the first listing returns these sessions
+            Ok((device_id, watched, _)) => { watched_devices.insert(device_id, watched); }
+            // one broken device shouldn't hide the sessions of the others
+            Err(e) => eprintln!("[AudioMonitor] Failed to watch an audio device: {:?}", e),
+        }
+    }
+
+    // existing sessions are registered, the backend can be used now
+    let _ = ready.send(Ok(()));
+
+    // messages taken out of the channel early to look for newer slider updates, they are handled before the channel again
+    let mut pending: VecDeque<MonitorThreadMessage> = VecDeque::new();
+  
+    // The main loop receives and processes event data sent by the COM callbacks and the commands sent by the backend.
+        // this loop is responsible for doing the same things we did to the already existing sessions in the previous code to new created sessions.
+    // The thread sleeps on the channel until there is something to do, shutdown is a message like everything else.
+    loop {
+
+        let message = match pending.pop_front() {
+            Some(message) => message,
+            None => match monitor_loop_receiver.recv() {
+                Ok(message) => message,
+                Err(mpsc::RecvError) => {
+                    eprintln!("[AudioMonitor] MPSC channel disconnected. Exiting loop.");
+                    break;
+                }
+            }
+        };
+
+        match message {
+            MonitorThreadMessage::SessionCreated { device_id, data_flow, control: session_control } => {
+                let details = match get_session_details(&session_control, &device_id, data_flow) {
+                    Ok(details) => if registry.contains_key(&details.session_uid) {
+                        continue;
+                    }else {details}
+                    Err(e) => {
+                        eprintln!("[AudioMonitor] Failed to get details of a new session on {}: {:?}", device_id, e);
+                        continue
+                    }
+                };
+                if register_session(session_control, &details, &events, &monitor_loop_sender, &mut registry) {
+                    events.dispatch(SessionEvent::Created(details));
+                }
+            }
+
+            MonitorThreadMessage::ExistingSessionClosed(closed_session_uid, reason) => {
+                close_session(&closed_session_uid, reason, &mut registry, &events);
+            }
+
+            // the notification doesn't say much, so we look at the device again and start or stop watching it
+            MonitorThreadMessage::DeviceChanged(device_id) => {
+                match find_active_device(&device_enumerator, &device_id) {
+                    Some(device) if !watched_devices.contains_key(&device_id) => {
+                        match watch_device(&device, &events, &monitor_loop_sender, &mut registry) {
+                            Ok((device_id, watched, sessions)) => {
+                                watched_devices.insert(device_id.clone(), watched);
+                                match get_device_details(&device_enumerator, &device) {
+                                    Ok(details) => events.dispatch(SessionEvent::DeviceAdded(details)),
+                                    Err(e) => eprintln!("[AudioMonitor] Failed to get details of device {}: {:?}", device_id, e),
+                                }
+                                // apps that kept a stream open on the device, the UI hasn't seen them yet
+                                for session in sessions {
+                                    events.dispatch(SessionEvent::Created(session));
+                                }
+                            }
+                            Err(e) => eprintln!("[AudioMonitor] Failed to watch device {}: {:?}", device_id, e),
+                        }
+                    }
+                    Some(_) => {} // already watched
+                    None => unwatch_device(&device_id, &mut watched_devices, &mut registry, &events),
+                }
+            }
+
+            // every active device is already watched, so there is nothing to re-bind, only the UI needs to know
+            MonitorThreadMessage::DefaultDeviceChanged { data_flow, role, device_id } => {
+                events.dispatch(SessionEvent::DefaultDeviceChanged(DefaultDeviceChangedPayload { device_id, data_flow, role }));
+            }
+
+            MonitorThreadMessage::ListSessions(reply) => {
+                let _ = reply.send(Ok(list_registered_sessions(&registry)));
+            }
+
+            MonitorThreadMessage::ListDevices(reply) => {
+                let _ = reply.send(list_active_devices(&device_enumerator).map_err(|e| AppError::com("Failed to list audio devices", &e)));
+            }
+
+            MonitorThreadMessage::SetVolume { pid, uid, volume, reply } => {
+                pending.extend(monitor_loop_receiver.try_iter());
+                let result = if has_newer_volume(&pending, pid, &uid) {
+                    // replaced by the newer one still in the queue, this caller gets the volume as it is now
+                    find_session(&registry, pid, &uid)
+                        .ok_or_else(|| AppError::SessionNotFound { uid: uid.clone() })
+                        .and_then(|session| read_session_volume(session, &uid))
+                } else {
+                    set_session_volume(&registry, pid, &uid, volume)
+                };
+                let _ = reply.send(result);
+            }
+
+            MonitorThreadMessage::SetMute { pid, uid, mute, reply } => {
+                let _ = reply.send(set_session_mute(&registry, pid, &uid, mute));
+            }
+
+            MonitorThreadMessage::GetChannelVolumes { pid, uid, reply } => {
+                let _ = reply.send(get_channel_volumes(&registry, pid, &uid));
+            }
+
+            MonitorThreadMessage::SetChannelVolumes { pid, uid, volumes, reply } => {
+                pending.extend(monitor_loop_receiver.try_iter());
+                let result = if has_newer_channel_volumes(&pending, pid, &uid) {
+                    Ok(())
+                } else {
+                    set_channel_volumes(&registry, pid, &uid, &volumes)
+                };
+                let _ = reply.send(result);
+            }
+
+            MonitorThreadMessage::GetMasterVolume { device_id, reply } => {
+                let result = find_watched_device(&watched_devices, &device_id).and_then(|device| {
+                    get_master_volume(&device.endpoint_volume, &device_id).map_err(|e| AppError::com(format!("Failed to get the master volume of {}", device_id), &e))
+                });
+                let _ = reply.send(result);
+            }
+
+            MonitorThreadMessage::SetMasterVolume { device_id, volume, reply } => {
+                pending.extend(monitor_loop_receiver.try_iter());
+                let result = if has_newer_master_volume(&pending, &device_id) {
+                    Ok(())
+                } else {
+                    find_watched_device(&watched_devices, &device_id).and_then(|device| {
+                        set_master_volume(&device.endpoint_volume, volume).map_err(|e| AppError::com(format!("Failed to set the master volume of {}", device_id), &e))
+                    })
+                };
+                let _ = reply.send(result);
+            }
+
+            MonitorThreadMessage::SetMasterMute { device_id, mute, reply } => {
+                let result = find_watched_device(&watched_devices, &device_id).and_then(|device| {
+                    set_master_mute(&device.endpoint_volume, mute).map_err(|e| AppError::com(format!("Failed to set the master mute of {}", device_id), &e))
+                });
+                let _ = reply.send(result);
+            }
+
+            MonitorThreadMessage::GetIconSource { pid, uid, reply } => {
+                let _ = reply.send(get_icon_source(&registry, pid, &uid));
+            }
+
+            MonitorThreadMessage::ReadLevels(reply) => {
+                let _ = reply.send(Ok(meters::read_levels(&registry, &watched_devices)));
+            }
+
+            MonitorThreadMessage::Shutdown => {
+                break;
+            }
+        }
+        
+    }
+
+    unsafe { let _ = device_enumerator.UnregisterEndpointNotificationCallback(&device_notifier); }
+    for (_id, device) in watched_devices.drain() {
+        device.unregister();
+    }
+    for (_id, session) in registry.drain() {
+        unsafe { let _ = session.control.UnregisterAudioSessionNotification(&session.listener); }
+    }
+    unsafe { CoUninitialize(); }
+
+}
+
+
+
+pub struct WasapiBackend {
+    // all the commands are sent to the COM worker through this
+    worker_sender: Sender<MonitorThreadMessage>,
+    events: EventDispatcher,
+    // set if the worker could not start, every command reports it instead of silently doing nothing
+    setup_error: Option<AppError>,
+    worker: Mutex<Option<thread::JoinHandle<()>>>, // taken by the first shutdown
+}
+
+impl WasapiBackend {
+    // starts the COM worker, it owns the sessions for the whole life of the backend
+    pub fn new() -> Self {
+        let events = EventDispatcher::default();
+        let (worker_sender, worker_receiver) = mpsc::channel::<MonitorThreadMessage>();
+        let (ready_sender, ready_receiver) = mpsc::channel::<std::result::Result<(), AppError>>();
+
+        let worker = {
+            let (events, sender) = (events.clone(), worker_sender.clone());
+            thread::spawn(move || com_worker_loop(events, sender, worker_receiver, ready_sender))
+        };
+
+        // wait until the existing sessions are registered so the first listing is complete
+        let setup_error = match ready_receiver.recv() {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e),
+            Err(_) => Some(AppError::backend("COM worker exited before it was ready")),
+        };
+        if let Some(e) = &setup_error {
+            eprintln!("[AudioMonitor] {}", e);
+        }
+
+        Self { worker_sender, events, setup_error, worker: Mutex::new(Some(worker)) }
+    }
+
+    // sends a command to the COM worker and blocks until it answers, async callers should go through spawn_blocking
+    // so they don't hold up a runtime worker, but calling it from anywhere is safe
+    fn request<T>(&self, message: impl FnOnce(Reply<T>) -> MonitorThreadMessage) -> std::result::Result<T, AppError> {
+        if let Some(e) = &self.setup_error {
+            return Err(e.clone());
+        }
+
+        let (reply_sender, reply_receiver) = mpsc::channel();
+        self.worker_sender
+            .send(message(reply_sender))
+            .map_err(|_| AppError::backend("COM worker is not running"))?;
+        reply_receiver.recv().map_err(|_| AppError::backend("COM worker stopped before answering"))?
+    }
+}
+
+// only a fallback, the app and the daemon call shutdown on exit: the backend is shared by the commands,
+// the level meter and the monitor, so the last clone can be dropped anywhere (or never, when the process exits first)
+impl Drop for WasapiBackend {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl AudioBackend for WasapiBackend {
+
+    fn list_sessions(&self) -> std::result::Result<Vec<SessionDetails>, AppError> {
+        self.request(MonitorThreadMessage::ListSessions)
+    }
+
+    fn list_devices(&self) -> std::result::Result<Vec<DeviceDetails>, AppError> {
+        self.request(MonitorThreadMessage::ListDevices)
+    }
+
+    fn set_volume(&self, pid: u32, uid: &str, volume: f32) -> std::result::Result<VolumeChangedPayload, AppError> {
+        self.request(|reply| MonitorThreadMessage::SetVolume { pid, uid: uid.to_string(), volume, reply })
+    }
+
+    fn set_mute(&self, pid: u32, uid: &str, mute: bool) -> std::result::Result<VolumeChangedPayload, AppError> {
+        self.request(|reply| MonitorThreadMessage::SetMute { pid, uid: uid.to_string(), mute, reply })
+    }
+
+    fn get_channel_volumes(&self, pid: u32, uid: &str) -> std::result::Result<Vec<f32>, AppError> {
+        self.request(|reply| MonitorThreadMessage::GetChannelVolumes { pid, uid: uid.to_string(), reply })
+    }
+
+    fn set_channel_volumes(&self, pid: u32, uid: &str, volumes: &[f32]) -> std::result::Result<(), AppError> {
+        self.request(|reply| MonitorThreadMessage::SetChannelVolumes { pid, uid: uid.to_string(), volumes: volumes.to_vec(), reply })
+    }
+
+    fn get_master_volume(&self, device_id: &str) -> std::result::Result<MasterVolume, AppError> {
+        self.request(|reply| MonitorThreadMessage::GetMasterVolume { device_id: device_id.to_string(), reply })
+    }
+
+    fn set_master_volume(&self, device_id: &str, volume: f32) -> std::result::Result<(), AppError> {
+        self.request(|reply| MonitorThreadMessage::SetMasterVolume { device_id: device_id.to_string(), volume, reply })
+    }
+
+    fn set_master_mute(&self, device_id: &str, mute: bool) -> std::result::Result<(), AppError> {
+        self.request(|reply| MonitorThreadMessage::SetMasterMute { device_id: device_id.to_string(), mute, reply })
+    }
+
+    fn icon_source(&self, pid: u32, uid: &str) -> std::result::Result<IconSource, AppError> {
+        self.request(|reply| MonitorThreadMessage::GetIconSource { pid, uid: uid.to_string(), reply })
+    }
+
+    fn read_levels(&self) -> std::result::Result<AudioLevels, AppError> {
+        self.request(MonitorThreadMessage::ReadLevels)
+    }
+
+    // the worker unregisters every listener and uninitializes COM before it exits, later commands get "COM worker is not running"
+    fn shutdown(&self) {
+        let _ = self.worker_sender.send(MonitorThreadMessage::Shutdown); // fails only if the worker already exited
+        let worker = self.worker.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        if let Some(worker) = worker {
+            let _ = worker.join();
+        }
+    }
+
+    fn subscribe(&self, on_event: SessionEventHandler) -> std::result::Result<Subscription, AppError> {
+        if let Some(e) = &self.setup_error {
+            return Err(e.clone());
+        }
+
+        let id = self.events.add(on_event);
+        let events = self.events.clone();
+        Ok(Subscription::new(move || events.remove(id)))
+    }
+}
@@ -1,124 +1,22 @@
 // STEP 0: Import necessary modules and types
 use std::{
-    collections::HashMap, string::FromUtf16Error, sync::{
-        atomic::{AtomicBool, Ordering as AtomicOrdering}, mpsc::{self, Receiver, Sender}, Arc, Mutex // For shutdown signal
-    }, thread
-};
-use tauri::{AppHandle, Emitter}; // To communicate with the frontend
-
-
-use windows::{
-    core::{implement, Interface, Ref, Result, BOOL, GUID, PCWSTR, PWSTR}, // Core Windows interface types
-    Win32::{Foundation::{CloseHandle, HANDLE}, Media::Audio::{
-        eConsole, eRender, AudioSessionDisconnectReason, AudioSessionState, AudioSessionStateActive, AudioSessionStateExpired, AudioSessionStateInactive,
-        Endpoints::IAudioEndpointVolume, IAudioSessionControl, IAudioSessionControl2, IAudioSessionEnumerator, IAudioSessionEvents, 
-        IAudioSessionEvents_Impl, IAudioSessionManager2, IAudioSessionNotification, IAudioSessionNotification_Impl, 
-        IMMDeviceEnumerator, ISimpleAudioVolume, MMDeviceEnumerator // audio session states
-     }, System::{Com::{
-        CoCreateInstance, CoInitializeEx, CoTaskMemFree, CoUninitialize, CLSCTX_ALL, COINIT_APARTMENTTHREADED, COINIT_MULTITHREADED // COM threading model (apartment threaded)
-     }, Diagnostics::ToolHelp::{
-        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS 
-     },},}
+    sync::{atomic::{AtomicUsize, Ordering}, mpsc, Arc, Mutex},
+    thread,
 };
+use crate::event_sink::EventSink; // To communicate with the frontend (or any other front end)
 
-use tauri::Manager;
 use tokio::net::{TcpListener, TcpStream}; // Provides the TCP listener for incoming connections.
 use futures_util::stream::StreamExt; // Extension trait for working with streams (like incoming messages).
 use futures_util::sink::SinkExt; // Extension trait for sending messages (sinking data).
 use std::net::SocketAddr; // Standard type for storing IP addresses and ports.
-use tokio_tungstenite::{WebSocketStream, accept_async, tungstenite::Error}; // Core type definitions for the async WebSocket stream handler.
+use tokio_tungstenite::accept_async; // Upgrades an accepted TCP stream to a WebSocket stream.
 use tokio_tungstenite::tungstenite::Message; // Type used to represent a WebSocket frame (Text, Binary, Ping, Close, etc.).
-use tokio::time::{sleep, Duration};
+use tokio::time::{timeout, Duration};
 use tokio::sync::{broadcast};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
 use crate::ExtensionData; // enum defined in lib.rs to wrap data received by websocket_server function via an mpsc channel from a command function
-
-fn get_process_name_by_id(process_id: u32) -> Result<Option<String>> {
-
-
-    unsafe {
-        // Create a snapshot of all processes.
-        // This is a point-in-time capture of all running processes in the system
-        let snapshot: HANDLE = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0)?;
-        // Check if the snapshot is valid
-        if snapshot.is_invalid() {
-            return Ok(None);
-        }
-
-
-
-        // Prepare process_entry to use it later to hold information.
-        // PROCESSENTRY32W: Windows API structure that holds information about a process, including its executable name, process ID, and other attributes. 
-        //It's commonly used with functions like Process32FirstW and Process32NextW 
-        let mut process_entry = PROCESSENTRY32W {
-            // dwSize: represents the size of the PROCESSENTRY32W structure in bytes. it's mandatory to set dwSize to the size of the structure.
-            dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32, // computes the size of the PROCESSENTRY32W structure at compile time. 
-            ..Default::default() //  initializes all other fields of the PROCESSENTRY32W structure to their default values.
-        };
-
-
-
-        // here we will use snapshot wich holds all the process and then we get the first process info by using Process32FirstW and then storing the info to process_entry.
-        // Process32FirstW: Retrieves information about the first process encountered in a system snapshot.
-        Process32FirstW(
-            snapshot, // A handle to the snapshot obtained from CreateToolhelp32Snapshot (all the snapshots captured with "let snapshot: HANDLE" )
-            &mut process_entry // A mutable reference to a PROCESSENTRY32W structure that will receive information about the process. (the thing that will hold info)
-        )?;
-
-
-        // Determines if the process retrieved matches the target process ID. (process_id IS THE ID OF THE PROCESS THAT OWNS THE AUDIO SESSION that we got in the code above).
-        if process_entry.th32ProcessID == process_id { // we check if The process ID of the current process in the snapshot == The target process ID we're searching for.
-
-            // Converts the process's executable name from UTF-16 to a Rust String.
-            let name_slice: Vec<u16> = process_entry // (at this point process_entry holds the name of the process that we need to convert to rust string with the next steps below:)
-
-                .szExeFile // .szExeFile makes process_entry a fixed-size array representing the process name in UTF-16.
-
-                .iter() // Iterate over process_entry.szExeFile wich is a fixed-size array
-
-                .take_while(|&&c| c != 0) // collect characters until the null terminator (\0) is encountered.
-
-                .copied() // ensures that the collected characters are owned and not references.
-
-                .collect(); // gathers them into a Vec<u16>.
-
-            // now at this point name_slice is a Vec<u16> of collected characters that represent the process name that we need to convert to a rust string
-
-            let exe_name = String::from_utf16_lossy(&name_slice); // converts the UTF-16 vector to a Rust String, replacing any invalid characters with the Unicode replacement character.
-
-            // Releases the handle to the snapshot to free system resources.
-            let _ = CloseHandle(snapshot); // this returns a result so it can fail but the fail is not critical so we ignore it by assignint it to let _
-
-            // Returns the name of the executable if the process ID matches.
-            return Ok(Some(exe_name)); // Wraps the executable name in Some and then in Ok to indicate a successful result.
-        }
-
-
-
-        
-        // This loop iterates over the remaining processes in the snapshot.
-        // Process32NextW advances to the next process in the snapshot and fills in process_entry with its details.
-        while Process32NextW(snapshot, &mut process_entry).is_ok() {// .is_ok() checks if the call succeeded. When there are no more processes or an error occurs, the loop ends
-
-            // we do the same thing we did above jsut this time in a loop
-            if process_entry.th32ProcessID == process_id { // If it matches, the target process has been found; if not, the loop continues with the next process.
-
-                let name_slice: Vec<u16> = process_entry
-                    .szExeFile
-                    .iter()
-                    .take_while(|&&c| c != 0)
-                    .copied()
-                    .collect();
-                let exe_name = String::from_utf16_lossy(&name_slice);
-                let _ = CloseHandle(snapshot);
-                return Ok(Some(exe_name)); // This exits the function immediately, so no further processes are checked.
-            }
-        } // If no match is found in the current iteration, the loop continues with the next process.
-
-        // No matching process found.
-        let _ = CloseHandle(snapshot);//After the loop finishes (i.e., when there are no more processes in the snapshot or an error occurs), the code closes the snapshot handle (free resoursses).
-        Ok(None) // It then returns Ok(None), indicating that no process with the specified process_id was found.
-    }
-}
+use crate::audio_backend::{SessionEvent, SharedBackend};
+use crate::error::AppError;
 
 #[derive(Debug, serde::Deserialize, serde::Serialize, Clone)] // getting data outside the tauri app means we need to Deserialize that data to work with it inside here
 // struct to hold tab info sent from the browser extension and send it to the frontend 
@@ -152,458 +50,209 @@ pub enum BrowserMessage {
 }
 
 
-#[derive(Debug, serde::Serialize, Clone)] // For serializing session details to JSON
-// Struct to hold session details to send to the frontend
-pub struct SessionDetails {
-    #[serde(rename = "pid")] // <-- RENAME FOR SERIALIZATION
-    pub process_id: u32,
-
-    #[serde(rename = "uid")] // <-- RENAME FOR SERIALIZATION
-    pub session_uid: String,
-
-    #[serde(rename = "name")] // <-- RENAME FOR SERIALIZATION
-    pub process_name: String,
-
-    #[serde(rename = "volume")] // <-- RENAME FOR SERIALIZATION
-    pub session_volume: f32,
-
-    pub is_muted: bool,
-    pub is_active: bool,
+// everything the monitor thread waits for: events from the backend, or the order to stop
+// Shutdown is sent once, boxing every event just to make it smaller isn't worth it
+#[allow(clippy::large_enum_variant)]
+pub enum MonitorMessage {
+    Session(SessionEvent),
+    Shutdown,
 }
 
-#[derive(Debug, serde::Serialize, Clone)]
-pub struct VolumeChangedPayload {
-    // Use serde to rename the field to match the frontend's expectation 
-    #[serde(rename = "uid")]
-    session_uid: String,
-    #[serde(rename = "newVolume")]
-    volume: f32,
-    #[serde(rename = "isMuted")]
-    is_muted: bool, 
+// Handle to the running monitor thread. Stopping it is a message on the same channel the events come from,
+// so the thread wakes up right away instead of noticing a flag on its next poll.
+pub struct MonitorHandle {
+    sender: mpsc::Sender<MonitorMessage>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>, // Mutex so the handle can live in tauri's manage store and be stopped through a shared reference
 }
 
-#[derive(Debug, serde::Serialize, Clone)]
-pub struct SessionStatePayload {
-    #[serde(rename = "uid")]
-    session_uid: String,
-    #[serde(rename = "is_active")]
-    is_active: bool,
-}
-
-
-enum MonitorThreadMessage {
-    SessionCreated(IAudioSessionControl),
-    ExistingSessionClosed(String),
+impl MonitorHandle {
+    // asks the monitor to stop and waits until it has dropped its subscription
+    pub fn shutdown(&self) {
+        let _ = self.sender.send(MonitorMessage::Shutdown); // fails only if the thread already exited
+        let thread = self.thread.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        if let Some(thread) = thread {
+            if thread.join().is_err() {
+                eprintln!("[AudioMonitor] Monitor thread panicked.");
+            }
+        }
+    }
 }
 
-
-
-
-#[implement(IAudioSessionEvents)]
-struct IndividualSessionEventsListener {
-    session_uid: String,
-    app_handle: AppHandle,
-    monitor_loop_sender: Sender<MonitorThreadMessage>,
+// starts `monitor_thread_loop` on its own OS thread
+pub fn spawn_monitor(event_sink: Arc<dyn EventSink>, backend: SharedBackend) -> MonitorHandle {
+    // To safely communicate between the backend callback threads and our main monitor loop thread,
+    // we use an MPSC channel. The backend callbacks and the handle send to it, the loop receives.
+    let (sender, receiver) = mpsc::channel::<MonitorMessage>();
+    let monitor_loop_sender = sender.clone();
+    let thread = thread::spawn(move || monitor_thread_loop(event_sink, backend, monitor_loop_sender, receiver));
+    MonitorHandle { sender, thread: Mutex::new(Some(thread)) }
 }
 
-#[allow(non_snake_case)]
-impl IAudioSessionEvents_Impl for IndividualSessionEventsListener_Impl {
-    fn OnDisplayNameChanged(&self, _new_display_name: &PCWSTR, _event_context: *const GUID) -> Result<()> { 
-        Ok(()) 
-    }
-
-    fn OnIconPathChanged(&self, _new_icon_path: &PCWSTR, _event_context: *const GUID) -> Result<()> { 
-        Ok(()) 
-    }
+// loop that runs ina thread and forwards new opened or removed process audio instances (and their changes) to the frontend
+// the backend does the OS specific listening, this loop only turns its SessionEvents into tauri events
+pub fn monitor_thread_loop(event_sink: Arc<dyn EventSink>, backend: SharedBackend, monitor_loop_sender: mpsc::Sender<MonitorMessage>, monitor_loop_receiver: mpsc::Receiver<MonitorMessage>)  {
 
-    fn OnChannelVolumeChanged(&self, _channel_count: u32, _new_channel_volume_array: *const f32, _changed_channel: u32, _event_context: *const GUID) -> Result<()> {
-        Ok(()) 
-    }
-
-    fn OnGroupingParamChanged(&self, _new_grouping_param: *const GUID, _event_context: *const GUID) -> Result<()> { 
-        Ok(()) 
-    }
-
-    
-    fn OnSimpleVolumeChanged(&self, new_volume: f32, new_mute: BOOL, _event_context: *const GUID) -> Result<()> {
-        let payload = VolumeChangedPayload {
-            session_uid: self.session_uid.clone(),
-            volume: new_volume,
-            is_muted: new_mute.as_bool(), 
-        };
-        println!("[AudioMonitor] Emitting 'audio-session-volume-changed' for session: {}", payload.session_uid);
-        self.app_handle.emit("audio-session-volume-changed", payload).unwrap_or_else(|e| {        
-            eprintln!("Failed to emit volume change event: {:?}", e);
-        });
+    // The subscription keeps the backend listeners alive, dropping it at the end of this function unregisters them.
+    let _subscription = match backend.subscribe(Arc::new(move |event| {
+        if monitor_loop_sender.send(MonitorMessage::Session(event)).is_err() {
+            eprintln!("[AudioMonitor] Failed to send session event to main monitor loop.");
+        }
+    })) {
+        Ok(subscription) => subscription,
+        Err(e) => {
+            eprintln!("[AudioMonitor] Failed to subscribe to audio session events: {}", e);
+            return;
+        }
+    };
 
-        Ok(()) 
-    }
-    #[allow(non_upper_case_globals)] // Allow this lint for the duration of the function
-    fn OnStateChanged(&self, new_state: AudioSessionState ) -> Result<()> {
-
-        match new_state {
-            AudioSessionStateExpired => {
-                self.app_handle.emit("audio-session-closed", &self.session_uid).unwrap_or_else(|e| {
-                    eprintln!("[AudioMonitor] Error emitting session closed event for {}: {}", self.session_uid, e);
-                });
-                
-                if self.monitor_loop_sender.send(MonitorThreadMessage::ExistingSessionClosed(self.session_uid.clone())).is_err() {
-                    eprintln!("[AudioMonitor] Failed to send session closed message for {} to main loop.", self.session_uid);
-                }
+    // The main loop sleeps on the channel until the backend sends an event or the handle sends Shutdown.
+    loop {
+        match monitor_loop_receiver.recv() {
+            Ok(MonitorMessage::Session(SessionEvent::Created(details))) => {
+                println!("[AudioMonitor] Emitting 'audio-session-created' for session: {}", details.session_uid);
+                event_sink.emit("audio-session-created", &details);
             }
-            AudioSessionStateActive => {
-                let payload = SessionStatePayload {
-                    session_uid: self.session_uid.clone(),
-                    is_active: true,
-                };
-                self.app_handle.emit("session-state-changed", payload).unwrap_or_else(|e| {
-                    eprintln!("[AudioMonitor] Failed to emit active state for Session {}: {}", self.session_uid, e)
-                });
+            Ok(MonitorMessage::Session(SessionEvent::Closed(payload))) => {
+                // logged with the reason, "my app vanished from the list" is answered from here
+                println!("[AudioMonitor] Emitting 'audio-session-closed' for session: {} ({:?})", payload.session_uid, payload.reason);
+                event_sink.emit("audio-session-closed", payload);
             }
-            AudioSessionStateInactive => {
-                let payload = SessionStatePayload {
-                    session_uid: self.session_uid.clone(),
-                    is_active: false
-                };
-                self.app_handle.emit("session-state-changed", payload).unwrap_or_else(|e| {
-                    eprintln!("[AudioMonitor] Failed to emit inactive state for Session {}: {}", self.session_uid, e)
-                })
+            Ok(MonitorMessage::Session(SessionEvent::VolumeChanged(payload))) => {
+                println!("[AudioMonitor] Emitting 'audio-session-volume-changed' for session: {}", payload.session_uid);
+                event_sink.emit("audio-session-volume-changed", payload);
             }
-            _ => {
-                eprintln!("[AudioMonitor] Session '{}' received an unknown state: {:?}", self.session_uid, new_state.0);
+            Ok(MonitorMessage::Session(SessionEvent::StateChanged(payload))) => {
+                event_sink.emit("session-state-changed", payload);
             }
-        }
-
-        Ok(())
-    }
-
-    fn OnSessionDisconnected(&self, _disconnect_reason: AudioSessionDisconnectReason) -> Result<()> {
-        self.app_handle.emit("audio-session-closed", &self.session_uid).unwrap_or_else(|e| {
-            eprintln!("[AudioMonitor] Error emitting session disconnected event for {}: {}", self.session_uid, e);
-        });
-
-        if self.monitor_loop_sender.send(MonitorThreadMessage::ExistingSessionClosed(self.session_uid.clone())).is_err() {
-            eprintln!("[AudioMonitor] Failed to send session closed message for {} to main loop.", self.session_uid);
-        }
-
-        Ok(())
-    }
-}
-
-
-#[implement(IAudioSessionNotification)]
-struct GlobalAudioSessionNotifier  {
-    monitor_loop_sender: Sender<MonitorThreadMessage>,
-}
-#[allow(non_snake_case)]
-impl IAudioSessionNotification_Impl for GlobalAudioSessionNotifier_Impl  { 
-    // --- THIS IS THE KEY METHOD called by Windows when a NEW session is created ---
-    // This callback runs on a Windows thread. Send the new session to the main loop for processing.
-    fn OnSessionCreated(&self, new_session_control: Ref<'_, IAudioSessionControl>) -> Result<()> { 
-        // here for whatever reason new_session_control goes from type Ref<'_, IAudioSessionControl> to Option<IAudioSessionControl> hence why we handle Some()
-        if let Some(owned_session_control)  = new_session_control.clone() {
-            if self.monitor_loop_sender.send(MonitorThreadMessage::SessionCreated(owned_session_control)).is_err(){
-                eprintln!("[AudioMonitor] GlobalNotifier: Failed to send new session to main monitor loop.");
+            Ok(MonitorMessage::Session(SessionEvent::ChannelsChanged(payload))) => {
+                event_sink.emit("audio-session-channels-changed", payload);
+            }
+            Ok(MonitorMessage::Session(SessionEvent::Renamed(payload))) => {
+                event_sink.emit("audio-session-renamed", payload);
+            }
+            Ok(MonitorMessage::Session(SessionEvent::IconChanged(payload))) => {
+                event_sink.emit("audio-session-icon-changed", payload);
+            }
+            Ok(MonitorMessage::Session(SessionEvent::GroupingChanged(payload))) => {
+                event_sink.emit("audio-session-grouping-changed", payload);
+            }
+            Ok(MonitorMessage::Session(SessionEvent::DeviceAdded(device))) => {
+                println!("[AudioMonitor] Emitting 'device-added' for device: {}", device.id);
+                event_sink.emit("device-added", &device);
+            }
+            Ok(MonitorMessage::Session(SessionEvent::DeviceRemoved(device_id))) => {
+                println!("[AudioMonitor] Emitting 'device-removed' for device: {}", device_id);
+                event_sink.emit("device-removed", &device_id);
+            }
+            Ok(MonitorMessage::Session(SessionEvent::DefaultDeviceChanged(payload))) => {
+                event_sink.emit("default-device-changed", payload);
+            }
+            Ok(MonitorMessage::Session(SessionEvent::MasterVolumeChanged(payload))) => {
+                event_sink.emit("master-volume-changed", payload);
+            }
+            Ok(MonitorMessage::Shutdown) => {
+                break;
+            }
+            Err(mpsc::RecvError) => {
+                eprintln!("[AudioMonitor] MPSC channel disconnected. Exiting loop.");
+                break;
             }
         }
-        Ok(())
     }
 }
 
-unsafe fn take_pwstr_to_string(pwstr: PWSTR) -> Option<String> {
 
-    if pwstr.is_null(){
-        return None; // if pwstr is null we return None
-    }    
 
-    let owned_string = pwstr.to_string() // convert to string
-    .ok()
-    .filter(|s| !s.is_empty()); // check if the string is empty and returns None if it is
+// how long shutdown waits for the websocket tasks to send their close frames before giving up on them
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
 
-    CoTaskMemFree(Some(pwstr.as_ptr() as _)); // <- Frees the memory here
-    
-    owned_string
-     
+// Shared by every tokio task of the app. Tasks await `token.cancelled()` instead of polling a flag,
+// and are spawned on `tasks` so shutdown can wait until they have all finished.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    pub token: CancellationToken,
+    pub tasks: TaskTracker,
 }
 
-fn get_session_details(session_control: &IAudioSessionControl) -> Result<SessionDetails> {
-    let session_volume_control: ISimpleAudioVolume = session_control.cast()?;
-    let volume = unsafe {
-        session_volume_control.GetMasterVolume()?
-    };
-    let muted = unsafe {
-        session_volume_control.GetMute()?
-    };
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    #[allow(non_upper_case_globals)]
-    let active = unsafe {session_control.GetState()}.map(|state| {
-        match state {
-            AudioSessionStateActive => true,
-            _ => false, // here we only really need the AudioSessionStateInactive to be false but since we need to exhaust every other possible arm we group them all to be false 
+    // cancels every task and waits for them to drain (close frames sent, connections closed)
+    pub async fn shutdown(&self) {
+        self.token.cancel();
+        self.tasks.close();
+        if timeout(SHUTDOWN_DRAIN_TIMEOUT, self.tasks.wait()).await.is_err() {
+            eprintln!("[WebSocket] {} task(s) still running after {:?}, exiting anyway.", self.tasks.len(), SHUTDOWN_DRAIN_TIMEOUT);
         }
-    })?;
-
-
-    let session_control2: IAudioSessionControl2 = session_control.cast()?;
-    let id = unsafe {
-        session_control2.GetProcessId().unwrap_or(0)
-    };
-
-    let uid = unsafe {
-        let pwstr = session_control2.GetSessionInstanceIdentifier()?;
-        match take_pwstr_to_string(pwstr) { // this converts and frees the pwstr
-            Some(uid) => uid,
-            None => {
-                eprintln!("[AudioMonitor] Could not get a valid UID for a session. Skipping it.");
-                return Err(windows::core::Error::new(windows::Win32::Foundation::E_FAIL, "Session has no instance identifier."));
-            }
-        } 
-    };
-    
-/* 
-    let display_name = match pwstr_to_owned_string(display_name_pwstr).ok().flatten() {
-        Some(name) => name,
-        None => match get_process_name_by_id(id).ok().flatten() {
-            Some(name_by_id) => name_by_id,
-            None => "Unknown".to_string(),
-        }
-    };
-*/
-    let display_name: String  = unsafe {
-        let pwstr = session_control2.GetDisplayName()?;
-        take_pwstr_to_string(pwstr)
     }
-    .or_else(|| get_process_name_by_id(id).ok() // here if pwstr is none or string is empty we use this function to get another name
-    .flatten())
-    .unwrap_or_else(|| "unknown".to_string());
-    
-
-
-    Ok(SessionDetails { process_id: id, session_uid: uid, process_name: display_name, session_volume: volume, is_muted: muted.as_bool(), is_active: active })
-
 }
 
-// loop that runs ina thread and catchs new opened or removed process audio instances
-pub fn monitor_thread_loop(app_handle: AppHandle, shutdown_signal: Arc<AtomicBool>)  {
+// How many browser extensions are connected to the websocket server. The tab commands check it,
+// a command sent with nobody connected would otherwise be dropped by the broadcast channel without a word.
+#[derive(Clone, Default)]
+pub struct ExtensionConnections(Arc<AtomicUsize>);
 
-    unsafe { 
-        if let Err(e) = CoInitializeEx( None, COINIT_MULTITHREADED ).ok() {
-            eprintln!("[AudioMonitor] COM failed to initialize: {:?}", e);
-            return;
-        }
+impl ExtensionConnections {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    // To safely communicate between the Windows callback threads and our main monitor loop thread,
-    // we use an MPSC channel. The sender is for the callbacks, the receiver is for the loop.
-    let (monitor_loop_sender, monitor_loop_receiver) = mpsc::channel::<MonitorThreadMessage>();
-
-
-
-    // This will hold the single, global IAudioSessionNotification COM object after it's successfully registered.
-    // We store it here for two critical reasons:
-    // 1. Lifetime Management: Keeping it in this variable ensures the object stays alive for the entire
-    //    duration of the monitor thread, so Windows can call its `OnSessionCreated` method.
-    // 2. Cleanup: We need to access this exact object again during shutdown to unregister it.
-    // It's an `Option` because registration can fail, in which case it will remain `None`.
-    let mut global_notifier_com_object: Option<IAudioSessionNotification> = None;  
-
-
-    // This HashMap tracks all the individual audio sessions we are currently monitoring.
-    // - Key: The session's unique instance identifier (`session_uid`), which is a String.
-    //        This is the only way to reliably distinguish between different audio sessions.
-    // - Value: A tuple containing the live COM objects for that specific session:
-    //          1. `IAudioSessionControl`: Needed to unregister the listener.
-    //          2. `IAudioSessionEvents`: The listener object itself, which receives callbacks
-    //                                   like OnStateChanged and OnSimpleVolumeChanged.
-    // We store these objects here to keep them alive and to manage their lifecycle (adding new
-    // listeners for new sessions and removing/unregistering them when sessions close).
-    let mut active_individual_listeners: HashMap<String, (IAudioSessionControl, IAudioSessionEvents)> = HashMap::new();
-
-
-
-
-
-    let device_enumerator: IMMDeviceEnumerator = unsafe {
-        match CoCreateInstance(
-            &MMDeviceEnumerator,
-            None,
-            CLSCTX_ALL,) {
-                Ok(d) => d,
-                Err(e) => {
-                    eprintln!("Failed to create MMDeviceEnumerator instance: {:?}", e);
-                    return
-                }
-            }
-    };
-
-    let default_device = unsafe {
-        match device_enumerator.GetDefaultAudioEndpoint(
-            eRender,
-            eConsole,) {
-                Ok(d) => d,
-                Err(e) => {
-                    eprintln!("Failed to get DefaultAudioEndpoint: {:?}", e);
-                    return
-                }
-            }
-    };
-
-    let session_manager: IAudioSessionManager2 = unsafe {
-        match default_device.Activate(
-            CLSCTX_ALL,
-            None,) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("Failed to activate IAudioSessionManager2 on default device: {:?}", e);
-                    return
-                }
-            }
-    };
-
-    // We register COM listeners that Windows will call when audio session events happen.
-    // The main loop below processes the results from these listeners.
-    // (The COM callbacks are executed on separate, Windows-managed threads.)
-
-    // create an instance of the struct and convert it to a com object using .into()
-    let global_notifier: IAudioSessionNotification = GlobalAudioSessionNotifier { monitor_loop_sender: monitor_loop_sender.clone() }.into();
-    // register this com object with RegisterSessionNotification so that windows knows that it exists 
-    if unsafe { session_manager.RegisterSessionNotification(&global_notifier).is_ok() } {
-        // store it in a variable so that it keeps it alive because global_notifier will go out of scope outside the if statement
-        global_notifier_com_object = Some(global_notifier); 
-    } else {
-        eprintln!("[AudioMonitor] CRITICAL: Failed to register global session notifier.");
+    pub fn is_connected(&self) -> bool {
+        self.0.load(Ordering::SeqCst) > 0
     }
 
-    
-    let session_enumerator: IAudioSessionEnumerator = unsafe { 
-        match session_manager.GetSessionEnumerator() {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Failed to get SessionEnumerator: {:?}", e);
-                return
-            }
-        }
-    };
-
-    let session_count = unsafe { 
-        match session_enumerator.GetCount() {
-            Ok(s) => s,
-            Err(e) => {
-                eprintln!("Failed to get session count: {:?}", e);
-                return
-            }
-        }
-    };
-        
-    for i in 0..session_count {
-
-        let session_control: IAudioSessionControl = unsafe { 
-            match session_enumerator.GetSession(i) {
-                Ok(i) => i,
-                Err(e) => {
-                    eprintln!("Failed to Get Session i: {:?}", e);
-                    continue // if one session fails we skip it to the next session in the loop
-                }
-            }
-        };
-
-        let details = match get_session_details(&session_control) {
-            Ok(details) => details,
-            Err(e) => {
-                eprintln!("Error: {:?}", e);
-                continue // if one session fails to get details we again skip to the next
-            }
-        };
-
-        // create an instance of the struct and convert it to a com object using .into()
-        let listener: IAudioSessionEvents = IndividualSessionEventsListener{
-            session_uid: details.session_uid.clone(),
-            app_handle: app_handle.clone(),
-            monitor_loop_sender: monitor_loop_sender.clone(),
-        }.into();
-
-        // register this com object with RegisterAudioSessionNotification so that windows knows that it exists 
-        if unsafe { session_control.RegisterAudioSessionNotification(&listener).is_ok()} {
-            // store it in a hashmap so that it keeps it alive because global_notifier will go out of scope outside the if statement
-            active_individual_listeners.insert(details.session_uid.clone(), (session_control, listener));
-            // for each session i now we have registered it with com notifications when a change happens to it it will be detected
-            // as for these sessions getting to the frontend that is handelled by a tauri::command
-     
-        }
-    };
-
-  
-    // The main loop receives and processes event data sent by the COM callbacks.
-        // this loop is responsible for doing the same things we did to the already existing sessions in the previous code to new created sessions.
-    loop {
-
-
-        // For shutdown, we use a shared AtomicBool (`shutdown_signal`), not the MPSC channel.
-        // The main Tauri thread sets it to true, and this loop checks it to know when to exit.
-        if shutdown_signal.load(AtomicOrdering::Relaxed) {
-            break;
-
-        }
+    // counts the connection until the returned guard is dropped
+    fn connect(&self) -> ConnectionGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard(self.0.clone())
+    }
+}
 
-        match monitor_loop_receiver.try_recv() {
-            Ok(MonitorThreadMessage::SessionCreated(session_control)) => {
-                let details = match get_session_details(&session_control) {
-                    Ok(details) => if active_individual_listeners.contains_key(&details.session_uid) {
-                        continue;
-                    }else {details}
-                    Err(e) => {
-                        eprintln!("Error: {:?}", e);
-                        continue
-                    }
-                };
-                let listener : IAudioSessionEvents = IndividualSessionEventsListener{
-                    session_uid: details.session_uid.clone(),
-                    app_handle: app_handle.clone(),
-                    monitor_loop_sender: monitor_loop_sender.clone(),
-                }.into();
-                if unsafe {session_control.RegisterAudioSessionNotification(&listener).is_ok()} {
-                    active_individual_listeners.insert(details.session_uid.clone(), (session_control, listener));
-                    println!("[AudioMonitor] Emitting 'audio-session-created' for session: {}", details.session_uid);
-                    app_handle.emit("audio-session-created", &details).unwrap_or_else(|e| {
-                        eprintln!("Error: {:?}", e);
-                    });
-                }
-            }
+struct ConnectionGuard(Arc<AtomicUsize>);
 
-            Ok(MonitorThreadMessage::ExistingSessionClosed(closed_session_uid)) => {
-                if let Some((control,listener)) = active_individual_listeners.remove(&closed_session_uid) {
-                    unsafe {let _ = control.UnregisterAudioSessionNotification(&listener);}
-                }
-            }
-            Err(mpsc::TryRecvError::Empty) => {}
-            Err(mpsc::TryRecvError::Disconnected) => {
-                eprintln!("[AudioMonitor] MPSC channel disconnected. Exiting loop.");
-                break;
-            }
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
-        }
+// What the tab commands go through: the channel to the websocket server and whether an extension is there to get them.
+// The desktop app's commands and the daemon's control server both use it.
+#[derive(Clone)]
+pub struct ExtensionCommands {
+    sender: tokio::sync::mpsc::Sender<ExtensionData>,
+    connections: ExtensionConnections,
+}
 
-        thread::sleep(std::time::Duration::from_millis(200));
-        
+impl ExtensionCommands {
+    pub fn new(sender: tokio::sync::mpsc::Sender<ExtensionData>, connections: ExtensionConnections) -> Self {
+        Self { sender, connections }
     }
 
-    if let Some(global_notifier) = global_notifier_com_object.take() {
-        unsafe { let _ = session_manager.UnregisterSessionNotification(&global_notifier); }
-    }
-    for (_id, (control, listener)) in active_individual_listeners.drain() {
-        unsafe { let _ = control.UnregisterAudioSessionNotification(&listener); }
+    pub async fn send(&self, command: ExtensionData) -> Result<(), AppError> {
+        // the server broadcasts to the connected extensions, with none the command would vanish
+        if !self.connections.is_connected() {
+            return Err(AppError::ExtensionDisconnected);
+        }
+        self.sender.send(command).await.map_err(|_| AppError::ExtensionDisconnected) // the server is gone
     }
-    unsafe { CoUninitialize(); }
-
 }
 
+// where the browser extension connects, the extension only knows the default one
+pub const DEFAULT_EXTENSION_ADDR: &str = "127.0.0.1:8080";
 
+// SOUND_CONTROL_EXTENSION_ADDR overrides it, for an extension build that connects somewhere else
+pub fn extension_addr() -> String {
+    std::env::var("SOUND_CONTROL_EXTENSION_ADDR").unwrap_or_else(|_| DEFAULT_EXTENSION_ADDR.to_string())
+}
 
 // start a websocket server that connects and listens for audio info from the browser extension
-// we pass it app_handle to use it to send audio updates from the extension to the application UI
+// we pass it an event sink to use it to send audio updates from the extension to the application UI
 // also pass it a tokio Receiver to receive data from command functions
-pub async fn websocket_server(app_handle: AppHandle, shutdown_signal: Arc<AtomicBool>, mut command_receiver: tokio::sync::mpsc::Receiver<ExtensionData> ) {
-    let port = "127.0.0.1:8080";
+pub async fn websocket_server(addr: String, event_sink: Arc<dyn EventSink>, shutdown: Shutdown, mut command_receiver: tokio::sync::mpsc::Receiver<ExtensionData>, connections: ExtensionConnections) {
+    let port = addr.as_str();
 
     // Create a broadcast channel to distribute commands to all connected clients.
     // this is a one producer many consumers channel
@@ -616,12 +265,10 @@ pub async fn websocket_server(app_handle: AppHandle, shutdown_signal: Arc<Atomic
         Ok(listener) => listener,
         Err(e) => {
             // Log to terminal
-            eprintln!("Fatal Server Error: {}", e);
+            eprintln!("[WebSocket] Failed to listen on {}: {}", port, e);
             let error_msg = format!("Port is already in use: {}", port);
             // Tell the Vue UI so the user knows why it's not working
-            app_handle.emit("server-error", error_msg).unwrap_or_else(|e| {
-                        eprintln!("Error: {:?}", e);
-                    });
+            event_sink.emit("server-error", error_msg);
             return; // Exit the function gracefully instead of panicking
         }
     }; 
@@ -633,13 +280,14 @@ pub async fn websocket_server(app_handle: AppHandle, shutdown_signal: Arc<Atomic
             response = listener.accept() => { 
                 match response {
                     Ok((stream, addr)) => {
-                        let handle = app_handle.clone(); // we need to clone the handle becasue handle_connection task thread can be spawned every loop so we need a handle for every loop 
-                        let shutdown = shutdown_signal.clone(); // clone the shutdown so every task detects it and sends a close frame to its client to also shutdown garcefuly 
+                        let sink = event_sink.clone(); // we need to clone the sink becasue handle_connection task thread can be spawned every loop so we need a sink for every loop 
+                        let token = shutdown.token.clone(); // clone the token so every task detects the shutdown and sends a close frame to its client to also shutdown garcefuly 
                         let broadcast_receiver = command_broadcaster.subscribe(); // receives data from the "broadcast sender that itself receives data from command functions"
-                        tokio::spawn(handle_connection(handle, stream, addr, shutdown, broadcast_receiver)); // Spawn a new, separate async task to handle this specific connection. 
+                        shutdown.tasks.spawn(handle_connection(sink, stream, addr, token, broadcast_receiver, connections.clone())); // Spawn a new, separate async task to handle this specific connection. 
                                                   //This allows the main server loop to immediately go back to listening for more connections without being blocked by the new one
+                                                  // the tracker keeps count of it so shutdown can wait for its close frame
                     }
-                    Err(e) => { eprintln!("Error: {}", e); }
+                    Err(e) => { eprintln!("[WebSocket] Failed to accept a connection: {}", e); }
                 } 
                 
             }
@@ -650,15 +298,8 @@ pub async fn websocket_server(app_handle: AppHandle, shutdown_signal: Arc<Atomic
                 let _ = command_broadcaster.send(command);
             }
 
-            // case 2: check if we have a shutdown every 100 milliseconds to avoid 100% CPU usage. if the shutdown is true the server closes
-            _ = async {
-                loop {
-                    if shutdown_signal.load(AtomicOrdering::Relaxed) {
-                        break; // break this inner loop
-                    }
-                    sleep(Duration::from_millis(100)).await;
-                }
-            } => {
+            // case 2: the token is cancelled, the server closes. this wakes up right away, nothing is polled
+            _ = shutdown.token.cancelled() => {
                 println!("[WebSocket] Shutdown signal received. Exiting server loop.");
                 break; // break the main loop
             }
@@ -669,10 +310,11 @@ pub async fn websocket_server(app_handle: AppHandle, shutdown_signal: Arc<Atomic
 
 
 // handle the stream channel to receive and send data  
-async fn handle_connection(app_handle: AppHandle, stream: TcpStream, addr: SocketAddr, shutdown_signal: Arc<AtomicBool>, mut command_broadcast_receiver: tokio::sync::broadcast::Receiver<ExtensionData>) {
+async fn handle_connection(event_sink: Arc<dyn EventSink>, stream: TcpStream, addr: SocketAddr, shutdown: CancellationToken, mut command_broadcast_receiver: tokio::sync::broadcast::Receiver<ExtensionData>, connections: ExtensionConnections) {
     
     // establish connection to the stream, this will be the channel where audio data will flow 
     if let Ok(ws_stream) =  accept_async(stream).await {
+        let _connected = connections.connect(); // counted until this block ends, however the connection ends
         // split the stream channel into two parts: a writer (for sending) and a reader (for receiving)
         let (mut write, mut read) = ws_stream.split();
 
@@ -683,15 +325,8 @@ async fn handle_connection(app_handle: AppHandle, stream: TcpStream, addr: Socke
                 biased; // polls the cases in order so this will check the shutdown first
 
 
-                _ = async { // select! polls hte future retrurned by the shutdown loop, if its 'true' then it executes the promise which is sending close to the client and break the loop
-
-                    loop {
-                        if shutdown_signal.load(AtomicOrdering::Relaxed) {
-                            break; // break this inner loop
-                        }
-                        sleep(Duration::from_millis(100)).await;
-                    }
-                } => {
+                // once the token is cancelled this completes and we send close to the client and break the loop
+                _ = shutdown.cancelled() => {
                     // send a closing message to the client (extension) to signal it that the server is closing
                     let _ = write.send(Message::Close(None)).await;
                     break; // break the main loop
@@ -713,9 +348,7 @@ async fn handle_connection(app_handle: AppHandle, stream: TcpStream, addr: Socke
                                             match browser_message { 
                                                 BrowserMessage::AudioTabs(tabs_payload) => {
                                                     // payload here is a "vec<AudioTab>"
-                                                    app_handle.emit("extension-audio-tabs", tabs_payload).unwrap_or_else(|e| {
-                                                    eprintln!("Error: {:?}", e);
-                                                    });
+                                                    event_sink.emit("extension-audio-tabs", tabs_payload);
                                                 }
                                                 
                                                 BrowserMessage::Ping(ping_payload) => {
@@ -782,7 +415,121 @@ async fn handle_connection(app_handle: AppHandle, stream: TcpStream, addr: Socke
 
     } else {
         // Handle the error case
-        eprintln!("failed to establish connection to {}", addr);
+        eprintln!("[WebSocket] Failed to establish the websocket connection with {}", addr);
     };
     
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_backend::{mock::MockBackend, DataFlow, DeviceDetails, DeviceRole, DeviceState, SessionCloseReason, SessionDetails};
+    use crate::event_sink::RecordingSink;
+    use std::time::Duration;
+
+    #[test]
+    fn forwards_backend_events_to_the_sink_until_shutdown() {
+        let backend = MockBackend::new();
+        let recorder = Arc::new(RecordingSink::new());
+
+        let monitor = spawn_monitor(recorder.clone(), Arc::new(backend.clone()));
+        // the monitor subscribes from its own thread, wait for it before scripting sessions
+        while backend.subscriber_count() == 0 {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        backend.add_session(SessionDetails::test(10, "a"));
+        backend.change_volume("a", 0.5, true);
+        backend.remove_session("a", SessionCloseReason::DeviceRemoval);
+
+        let created = recorder.wait_for("audio-session-created", Duration::from_secs(2)).unwrap();
+        assert_eq!(created.payload["uid"], "a");
+        let changed = recorder.wait_for("audio-session-volume-changed", Duration::from_secs(2)).unwrap();
+        assert_eq!(changed.payload["newVolume"], 0.5);
+        let closed = recorder.wait_for("audio-session-closed", Duration::from_secs(2)).unwrap();
+        assert_eq!(closed.payload, serde_json::json!({ "uid": "a", "reason": "device_removal" }));
+
+        backend.add_device(DeviceDetails {
+            id: "mock-headphones".to_string(),
+            name: "Headphones".to_string(),
+            state: DeviceState::Active,
+            data_flow: DataFlow::Render,
+            is_default_console: false,
+            is_default_multimedia: false,
+            is_default_communications: false,
+        }, vec![]);
+        backend.set_default_device(Some("mock-headphones"), DataFlow::Render, DeviceRole::Multimedia);
+        backend.change_master_volume("mock-headphones", 0.3, false);
+        backend.remove_device("mock-headphones");
+
+        assert_eq!(recorder.wait_for("device-added", Duration::from_secs(2)).unwrap().payload["id"], "mock-headphones");
+        let default_changed = recorder.wait_for("default-device-changed", Duration::from_secs(2)).unwrap();
+        assert_eq!(default_changed.payload, serde_json::json!({ "device_id": "mock-headphones", "data_flow": "render", "role": "multimedia" }));
+        let master = recorder.wait_for("master-volume-changed", Duration::from_secs(2)).unwrap();
+        assert_eq!(master.payload, serde_json::json!({ "device_id": "mock-headphones", "volume": 0.3f32, "is_muted": false }));
+        assert_eq!(recorder.wait_for("device-removed", Duration::from_secs(2)).unwrap().payload, "mock-headphones");
+
+        monitor.shutdown();
+        // the subscription is released with the monitor
+        assert_eq!(backend.subscriber_count(), 0);
+    }
+
+    #[test]
+    fn a_plugged_in_device_brings_its_sessions_to_the_ui() {
+        let backend = MockBackend::new();
+        let recorder = Arc::new(RecordingSink::new());
+        let monitor = spawn_monitor(recorder.clone(), Arc::new(backend.clone()));
+        while backend.subscriber_count() == 0 {
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        // an app kept its stream open on the headset while it was unplugged
+        let mut session = SessionDetails::test(10, "headset-call");
+        session.device_id = "mock-headset".to_string();
+        let headset = DeviceDetails {
+            id: "mock-headset".to_string(),
+            name: "Headset".to_string(),
+            state: DeviceState::Active,
+            data_flow: DataFlow::Render,
+            is_default_console: false,
+            is_default_multimedia: false,
+            is_default_communications: false,
+        };
+        backend.add_device(headset, vec![session]);
+
+        assert_eq!(recorder.wait_for("device-added", Duration::from_secs(2)).unwrap().payload["id"], "mock-headset");
+        let created = recorder.wait_for("audio-session-created", Duration::from_secs(2)).unwrap();
+        assert_eq!(created.payload["uid"], "headset-call");
+        assert_eq!(created.payload["device_id"], "mock-headset");
+        let names: Vec<String> = recorder.events().into_iter().map(|e| e.name).collect();
+        assert_eq!(names, vec!["device-added", "audio-session-created"]);
+
+        monitor.shutdown();
+    }
+
+    #[tokio::test]
+    async fn shutdown_waits_for_tracked_tasks_to_finish() {
+        let shutdown = Shutdown::new();
+        let (closed_sender, mut closed_receiver) = tokio::sync::mpsc::unbounded_channel();
+
+        for id in 0..3 {
+            let (token, closed_sender) = (shutdown.token.clone(), closed_sender.clone());
+            shutdown.tasks.spawn(async move {
+                token.cancelled().await;
+                // stands in for the close frame a connection sends on its way out
+                tokio::task::yield_now().await;
+                let _ = closed_sender.send(id);
+            });
+        }
+        drop(closed_sender);
+
+        shutdown.shutdown().await;
+
+        assert!(shutdown.tasks.is_empty());
+        let mut closed = Vec::new();
+        while let Ok(id) = closed_receiver.try_recv() {
+            closed.push(id);
+        }
+        closed.sort();
+        assert_eq!(closed, vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,82 @@
+// Headless mixer daemon: runs the audio monitor and the browser extension WebSocket server
+// without a window, so tab and session automation keeps working when nobody has the UI open.
+// Clients attach on the control connection (see control.rs): they send the backend's commands and
+// tab commands and get every event streamed back. The desktop app attaches to it when it finds it running.
+// Every event is also written to stdout as one JSON object per line, for tools that only read.
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use sound_control_panel_lib::{
+    audio_backend, audio_monitor, control,
+    event_sink::{BroadcastSink, EmittedEvent, EventSink},
+    ExtensionData,
+};
+
+// events kept for a client that is slow to read, past that it misses the oldest ones
+const EVENT_BUFFER: usize = 256;
+
+#[tokio::main]
+async fn main() {
+    let backend = audio_backend::default_backend();
+
+    // every event goes to the stdout printer and to each control connection, the monitor thread never waits on them
+    let (events, mut printed) = broadcast::channel::<EmittedEvent>(EVENT_BUFFER);
+    let event_sink: Arc<dyn EventSink> = Arc::new(BroadcastSink(events.clone()));
+
+    let shutdown = audio_monitor::Shutdown::new();
+    // the control connections send tab commands through this, like the desktop app's commands do
+    let (tab_data_sender, tab_data_receiver) = tokio::sync::mpsc::channel::<ExtensionData>(128);
+    let connections = audio_monitor::ExtensionConnections::new();
+    let extension = audio_monitor::ExtensionCommands::new(tab_data_sender, connections.clone());
+
+    let listing = backend.clone();
+    match tokio::task::spawn_blocking(move || listing.list_sessions()).await {
+        Ok(Ok(sessions)) => eprintln!("[Daemon] Monitoring {} existing audio sessions.", sessions.len()),
+        Ok(Err(e)) => eprintln!("[Daemon] Failed to list existing audio sessions: {}", e),
+        Err(e) => eprintln!("[Daemon] Listing the audio sessions failed: {}", e),
+    }
+
+    let printer = tokio::spawn(async move {
+        loop {
+            match printed.recv().await {
+                Ok(event) => match serde_json::to_string(&event) {
+                    Ok(line) => println!("{}", line),
+                    Err(e) => eprintln!("[Daemon] Failed to serialize event '{}': {}", event.name, e),
+                },
+                Err(broadcast::error::RecvError::Lagged(missed)) => eprintln!("[Daemon] {} events were not printed.", missed),
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let monitor = audio_monitor::spawn_monitor(event_sink.clone(), backend.clone());
+    shutdown.tasks.spawn(audio_monitor::websocket_server(audio_monitor::extension_addr(), event_sink.clone(), shutdown.clone(), tab_data_receiver, connections));
+
+    let control_addr = control::control_addr();
+    match tokio::net::TcpListener::bind(&control_addr).await {
+        Ok(listener) => {
+            eprintln!("[Daemon] Accepting control connections on {}.", control_addr);
+            shutdown.tasks.spawn(control::control_server(listener, backend.clone(), events.clone(), extension, shutdown.clone()));
+        }
+        Err(e) => eprintln!("[Daemon] Failed to listen for control connections on {}: {}", control_addr, e),
+    }
+
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        eprintln!("[Daemon] Failed to listen for the shutdown signal: {}", e);
+    }
+    eprintln!("[Daemon] Shutting down.");
+
+    // same shutdown path as closing the window in the desktop app
+    // waits for the servers and every connection to send its close frame
+    shutdown.shutdown().await;
+    // then the backend itself (the WASAPI COM worker), the monitor and the control connections were its last users
+    let _ = tokio::task::spawn_blocking(move || {
+        monitor.shutdown();
+        backend.shutdown();
+    })
+    .await;
+    // every sender is gone now, so the printer drains what is left and stops
+    // (bounded, a connection stuck past the drain timeout would still hold one)
+    drop(event_sink);
+    drop(events);
+    let _ = tokio::time::timeout(std::time::Duration::from_secs(1), printer).await;
+}
@@ -0,0 +1,455 @@
+// The daemon's control connection. The daemon owns the backend and the browser extension server,
+// the desktop app (or any other client) connects to it over a local websocket, sends it the backend's
+// commands and gets every event the daemon emits streamed back.
+//
+// client -> daemon  {"id": 1, "command": "set_volume", "args": {"pid": 10, "uid": "...", "volume": 0.5}}
+// daemon -> client  {"id": 1, "ok": {...}}  or  {"id": 1, "error": {"kind": "SessionNotFound", ...}}
+// daemon -> client  {"name": "audio-session-created", "payload": {...}}, the same lines the daemon prints
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{mpsc, Arc},
+};
+use futures_util::{sink::SinkExt, stream::StreamExt};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc::UnboundedReceiver, mpsc::UnboundedSender};
+use tokio::time::{timeout, Duration};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::{http, Message};
+use tokio_tungstenite::{accept_hdr_async, connect_async, MaybeTlsStream, WebSocketStream};
+use tokio_util::sync::CancellationToken;
+use crate::audio_backend::{
+    AudioBackend, AudioLevels, DeviceDetails, IconSource, MasterVolume, SessionDetails, SessionEventHandler, SharedBackend, Subscription,
+    VolumeChangedPayload,
+};
+use crate::audio_monitor::{ExtensionCommands, Shutdown};
+use crate::error::AppError;
+use crate::event_sink::{EmittedEvent, EventSink};
+use crate::ExtensionData;
+
+// not the extension's port, the daemon and a desktop app would both want that one
+pub const DEFAULT_CONTROL_ADDR: &str = "127.0.0.1:8081";
+
+// a daemon that is running answers right away, the desktop app should not wait long to find out there is none
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+// SOUND_CONTROL_DAEMON_ADDR moves it, the desktop app reads the same variable to find the daemon
+pub fn control_addr() -> String {
+    std::env::var("SOUND_CONTROL_DAEMON_ADDR").unwrap_or_else(|_| DEFAULT_CONTROL_ADDR.to_string())
+}
+
+// the AudioBackend calls a client can make, plus the tab commands that go on to the browser extension
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", content = "args", rename_all = "snake_case")]
+pub enum ControlCommand {
+    ListSessions,
+    ListDevices,
+    SetVolume { pid: u32, uid: String, volume: f32 },
+    SetMute { pid: u32, uid: String, mute: bool },
+    GetChannelVolumes { pid: u32, uid: String },
+    SetChannelVolumes { pid: u32, uid: String, volumes: Vec<f32> },
+    GetMasterVolume { device_id: String },
+    SetMasterVolume { device_id: String, volume: f32 },
+    SetMasterMute { device_id: String, mute: bool },
+    IconSource { pid: u32, uid: String },
+    ReadLevels,
+    SendToExtension(ExtensionData),
+}
+
+// serves the control connections until shutdown
+// the caller binds the listener, so a port already in use is reported where the daemon starts
+pub async fn control_server(listener: TcpListener, backend: SharedBackend, events: broadcast::Sender<EmittedEvent>, extension: ExtensionCommands, shutdown: Shutdown) {
+    loop {
+        tokio::select! {
+            response = listener.accept() => match response {
+                Ok((stream, addr)) => {
+                    // subscribed here so the client gets every event from the moment it connects
+                    let client = serve_client(stream, addr, backend.clone(), events.subscribe(), extension.clone(), shutdown.token.clone());
+                    shutdown.tasks.spawn(client);
+                }
+                Err(e) => eprintln!("[Control] Failed to accept a connection: {}", e),
+            },
+            _ = shutdown.token.cancelled() => break,
+        }
+    }
+}
+
+async fn serve_client(stream: TcpStream, addr: SocketAddr, backend: SharedBackend, mut events: broadcast::Receiver<EmittedEvent>, extension: ExtensionCommands, shutdown: CancellationToken) {
+    let ws_stream = match accept_hdr_async(stream, refuse_browsers).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            eprintln!("[Control] Handshake with {} failed: {}", addr, e);
+            return;
+        }
+    };
+    println!("[Control] {} connected.", addr);
+    let (mut write, mut read) = ws_stream.split();
+
+    loop {
+        let outgoing = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            message = read.next() => match message {
+                Some(Ok(Message::Text(text))) => match answer(&backend, &extension, &text).await {
+                    Some(reply) => reply,
+                    None => continue,
+                },
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue, // pings are answered by tungstenite
+                Some(Err(e)) => {
+                    eprintln!("[Control] Connection with {} failed: {}", addr, e);
+                    break;
+                }
+            },
+            event = events.recv() => match event {
+                Ok(event) => match serde_json::to_value(event) {
+                    Ok(event) => event,
+                    Err(_) => continue,
+                },
+                // the client keeps going without the events it missed, the next ones still describe the current state
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    eprintln!("[Control] {} fell behind and missed {} events.", addr, missed);
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        if write.send(Message::Text(outgoing.to_string().into())).await.is_err() {
+            break;
+        }
+    }
+
+    let _ = write.send(Message::Close(None)).await;
+    println!("[Control] {} disconnected.", addr);
+}
+
+// A browser puts an Origin header on every websocket handshake and other programs (the desktop app, scripts) don't.
+// Refusing it keeps any web page the user opens from driving the mixer through ws://127.0.0.1 (cross-site websocket hijacking)
+#[allow(clippy::result_large_err)] // the signature is tungstenite's handshake callback
+fn refuse_browsers(request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+    let Some(origin) = request.headers().get(http::header::ORIGIN) else { return Ok(response) };
+    eprintln!("[Control] Refused a connection from a web page ({:?}).", origin);
+    let mut refused = ErrorResponse::new(Some("Web pages can't control the mixer".to_string()));
+    *refused.status_mut() = http::StatusCode::FORBIDDEN;
+    Err(refused)
+}
+
+// runs one request, None when it has no id to answer to
+async fn answer(backend: &SharedBackend, extension: &ExtensionCommands, text: &str) -> Option<Value> {
+    let mut request: Value = match serde_json::from_str(text) {
+        Ok(request) => request,
+        Err(e) => {
+            eprintln!("[Control] Failed to parse request: {}", e);
+            return None;
+        }
+    };
+    let Some(id) = request.as_object_mut().and_then(|request| request.remove("id")).and_then(|id| id.as_u64()) else {
+        eprintln!("[Control] Ignoring a request without an id: {}", text);
+        return None;
+    };
+
+    let outcome = match serde_json::from_value::<ControlCommand>(request) {
+        Ok(command) => execute(backend, extension, command).await,
+        Err(e) => Err(AppError::InvalidArgument(format!("Unknown command: {}", e))),
+    };
+    Some(match outcome {
+        Ok(value) => json!({ "id": id, "ok": value }),
+        Err(error) => json!({ "id": id, "error": error }),
+    })
+}
+
+async fn execute(backend: &SharedBackend, extension: &ExtensionCommands, command: ControlCommand) -> Result<Value, AppError> {
+    if let ControlCommand::SendToExtension(data) = command {
+        return extension.send(data).await.map(|()| Value::Null);
+    }
+    // the backend blocks (COM calls, the pulse mainloop), same as in the tauri commands
+    let backend = backend.clone();
+    tokio::task::spawn_blocking(move || run(backend.as_ref(), command))
+        .await
+        .map_err(|e| AppError::backend(format!("Control command task failed: {}", e)))?
+}
+
+// the backend call behind a command, with its result as JSON
+fn run(backend: &dyn AudioBackend, command: ControlCommand) -> Result<Value, AppError> {
+    fn to_json(value: impl Serialize) -> Result<Value, AppError> {
+        serde_json::to_value(value).map_err(|e| AppError::backend(format!("Failed to serialize the result: {}", e)))
+    }
+    match command {
+        ControlCommand::ListSessions => to_json(backend.list_sessions()?),
+        ControlCommand::ListDevices => to_json(backend.list_devices()?),
+        ControlCommand::SetVolume { pid, uid, volume } => to_json(backend.set_volume(pid, &uid, volume)?),
+        ControlCommand::SetMute { pid, uid, mute } => to_json(backend.set_mute(pid, &uid, mute)?),
+        ControlCommand::GetChannelVolumes { pid, uid } => to_json(backend.get_channel_volumes(pid, &uid)?),
+        ControlCommand::SetChannelVolumes { pid, uid, volumes } => to_json(backend.set_channel_volumes(pid, &uid, &volumes)?),
+        ControlCommand::GetMasterVolume { device_id } => to_json(backend.get_master_volume(&device_id)?),
+        ControlCommand::SetMasterVolume { device_id, volume } => to_json(backend.set_master_volume(&device_id, volume)?),
+        ControlCommand::SetMasterMute { device_id, mute } => to_json(backend.set_master_mute(&device_id, mute)?),
+        ControlCommand::IconSource { pid, uid } => to_json(backend.icon_source(pid, &uid)?),
+        ControlCommand::ReadLevels => to_json(backend.read_levels()?),
+        ControlCommand::SendToExtension(_) => Err(AppError::InvalidArgument("Tab commands go to the extension server".to_string())),
+    }
+}
+
+type Reply = mpsc::Sender<Result<Value, AppError>>;
+
+// A connection to the daemon. An attached desktop app uses it as its backend, the daemon's backend does the work.
+// Requests block like the WASAPI backend's, so it is called from spawn_blocking like any other backend.
+#[derive(Clone)]
+pub struct ControlClient {
+    requests: UnboundedSender<(ControlCommand, Reply)>,
+}
+
+impl ControlClient {
+    // connects to a running daemon, its events go to `event_sink` until shutdown or until the daemon goes away
+    pub async fn connect(addr: &str, event_sink: Arc<dyn EventSink>, shutdown: Shutdown) -> Result<Self, AppError> {
+        let (ws_stream, _) = timeout(CONNECT_TIMEOUT, connect_async(format!("ws://{}", addr)))
+            .await
+            .map_err(|_| AppError::backend(format!("Timed out connecting to the daemon on {}", addr)))?
+            .map_err(|e| AppError::backend(format!("No daemon on {}: {}", addr, e)))?;
+
+        let (requests, request_receiver) = tokio::sync::mpsc::unbounded_channel();
+        shutdown.tasks.spawn(client_loop(ws_stream, request_receiver, event_sink, shutdown.token.clone()));
+        Ok(Self { requests })
+    }
+
+    // sends a command and blocks until the daemon answers
+    // a std channel carries the answer, so this is safe to call from a thread inside the runtime
+    pub fn request(&self, command: ControlCommand) -> Result<Value, AppError> {
+        let (reply_sender, reply_receiver) = mpsc::channel();
+        self.requests
+            .send((command, reply_sender))
+            .map_err(|_| AppError::backend("Not connected to the daemon"))?;
+        reply_receiver
+            .recv()
+            .map_err(|_| AppError::backend("Lost the connection to the daemon before it answered"))?
+    }
+
+    fn call<T: DeserializeOwned>(&self, command: ControlCommand) -> Result<T, AppError> {
+        serde_json::from_value(self.request(command)?).map_err(|e| AppError::backend(format!("Unexpected answer from the daemon: {}", e)))
+    }
+
+    pub async fn send_to_extension(&self, command: ExtensionData) -> Result<(), AppError> {
+        let client = self.clone();
+        tokio::task::spawn_blocking(move || client.request(ControlCommand::SendToExtension(command)))
+            .await
+            .map_err(|e| AppError::backend(format!("Control request task failed: {}", e)))?
+            .map(|_| ())
+    }
+}
+
+async fn client_loop(
+    ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    mut requests: UnboundedReceiver<(ControlCommand, Reply)>,
+    event_sink: Arc<dyn EventSink>,
+    shutdown: CancellationToken,
+) {
+    let (mut write, mut read) = ws_stream.split();
+    // the callers waiting for an answer, by request id
+    let mut pending: HashMap<u64, Reply> = HashMap::new();
+    let mut next_id: u64 = 0;
+
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                let _ = write.send(Message::Close(None)).await;
+                return;
+            }
+            Some((command, reply)) = requests.recv() => {
+                next_id += 1;
+                let mut request = match serde_json::to_value(&command) {
+                    Ok(request) => request,
+                    Err(e) => {
+                        let _ = reply.send(Err(AppError::backend(format!("Failed to serialize the command: {}", e))));
+                        continue;
+                    }
+                };
+                request["id"] = next_id.into();
+                if write.send(Message::Text(request.to_string().into())).await.is_err() {
+                    break;
+                }
+                pending.insert(next_id, reply);
+            }
+            message = read.next() => match message {
+                Some(Ok(Message::Text(text))) => receive(&text, &mut pending, event_sink.as_ref()),
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+
+    // dropping `pending` answers every waiting caller with an error
+    eprintln!("[Control] Lost the connection to the daemon.");
+    event_sink.emit("server-error", "Lost the connection to the sound control daemon");
+}
+
+// an answer goes to the caller waiting for it, anything else is an event for the sink
+fn receive(text: &str, pending: &mut HashMap<u64, Reply>, event_sink: &dyn EventSink) {
+    let mut message: Value = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => {
+            eprintln!("[Control] Failed to parse a message from the daemon: {}", e);
+            return;
+        }
+    };
+
+    let Some(id) = message.get("id").and_then(Value::as_u64) else {
+        match serde_json::from_value::<EmittedEvent>(message) {
+            Ok(event) => event_sink.emit_value(&event.name, event.payload),
+            Err(e) => eprintln!("[Control] Failed to parse an event from the daemon: {}", e),
+        }
+        return;
+    };
+    let Some(reply) = pending.remove(&id) else { return };
+
+    let outcome = match message.get_mut("error") {
+        Some(error) => Err(serde_json::from_value(error.take())
+            .unwrap_or_else(|e| AppError::backend(format!("Unreadable error from the daemon: {}", e)))),
+        None => Ok(message.get_mut("ok").map(Value::take).unwrap_or(Value::Null)),
+    };
+    let _ = reply.send(outcome);
+}
+
+impl AudioBackend for ControlClient {
+    fn list_sessions(&self) -> Result<Vec<SessionDetails>, AppError> {
+        self.call(ControlCommand::ListSessions)
+    }
+
+    fn list_devices(&self) -> Result<Vec<DeviceDetails>, AppError> {
+        self.call(ControlCommand::ListDevices)
+    }
+
+    fn set_volume(&self, pid: u32, uid: &str, volume: f32) -> Result<VolumeChangedPayload, AppError> {
+        self.call(ControlCommand::SetVolume { pid, uid: uid.to_string(), volume })
+    }
+
+    fn set_mute(&self, pid: u32, uid: &str, mute: bool) -> Result<VolumeChangedPayload, AppError> {
+        self.call(ControlCommand::SetMute { pid, uid: uid.to_string(), mute })
+    }
+
+    fn get_channel_volumes(&self, pid: u32, uid: &str) -> Result<Vec<f32>, AppError> {
+        self.call(ControlCommand::GetChannelVolumes { pid, uid: uid.to_string() })
+    }
+
+    fn set_channel_volumes(&self, pid: u32, uid: &str, volumes: &[f32]) -> Result<(), AppError> {
+        self.call(ControlCommand::SetChannelVolumes { pid, uid: uid.to_string(), volumes: volumes.to_vec() })
+    }
+
+    fn get_master_volume(&self, device_id: &str) -> Result<MasterVolume, AppError> {
+        self.call(ControlCommand::GetMasterVolume { device_id: device_id.to_string() })
+    }
+
+    fn set_master_volume(&self, device_id: &str, volume: f32) -> Result<(), AppError> {
+        self.call(ControlCommand::SetMasterVolume { device_id: device_id.to_string(), volume })
+    }
+
+    fn set_master_mute(&self, device_id: &str, mute: bool) -> Result<(), AppError> {
+        self.call(ControlCommand::SetMasterMute { device_id: device_id.to_string(), mute })
+    }
+
+    fn icon_source(&self, pid: u32, uid: &str) -> Result<IconSource, AppError> {
+        self.call(ControlCommand::IconSource { pid, uid: uid.to_string() })
+    }
+
+    fn read_levels(&self) -> Result<AudioLevels, AppError> {
+        self.call(ControlCommand::ReadLevels)
+    }
+
+    fn subscribe(&self, _on_event: SessionEventHandler) -> Result<Subscription, AppError> {
+        // the daemon's monitor already turns them into events, they arrive on the sink given to connect
+        Err(AppError::backend("Session events come from the daemon's connection, not a subscription"))
+    }
+}
+
+// where the desktop app's tab commands go: its own extension server, or the daemon's when it is attached to one
+#[derive(Clone)]
+pub enum ExtensionLink {
+    Local(ExtensionCommands),
+    Daemon(ControlClient),
+}
+
+impl ExtensionLink {
+    pub async fn send(&self, command: ExtensionData) -> Result<(), AppError> {
+        match self {
+            ExtensionLink::Local(extension) => extension.send(command).await,
+            ExtensionLink::Daemon(client) => client.send_to_extension(command).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_backend::mock::MockBackend;
+    use crate::audio_monitor::ExtensionConnections;
+    use crate::event_sink::{BroadcastSink, RecordingSink};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn an_attached_client_drives_the_daemon_backend() {
+        let backend = Arc::new(MockBackend::with_sessions(vec![SessionDetails::test(10, "a")]));
+        let shutdown = Shutdown::new();
+        let (events, _) = broadcast::channel(16);
+        let (tab_sender, _tab_receiver) = tokio::sync::mpsc::channel(1);
+        let extension = ExtensionCommands::new(tab_sender, ExtensionConnections::new());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        shutdown.tasks.spawn(control_server(listener, backend.clone(), events.clone(), extension, shutdown.clone()));
+
+        let recorder = Arc::new(RecordingSink::new());
+        let client = ControlClient::connect(&addr, recorder.clone(), shutdown.clone()).await.unwrap();
+
+        let remote = client.clone();
+        let (sessions, applied, missing) = tokio::task::spawn_blocking(move || {
+            (remote.list_sessions(), remote.set_volume(10, "a", 0.5), remote.set_mute(10, "gone", true))
+        })
+        .await
+        .unwrap();
+        let sessions = sessions.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].group_key, "render|exe|test.exe"); // the daemon's details come through whole
+        assert_eq!(applied.unwrap().volume, 0.5);
+        assert_eq!(backend.list_sessions().unwrap()[0].session_volume, 0.5);
+        assert_eq!(missing, Err(AppError::SessionNotFound { uid: "gone".to_string() }));
+
+        // no extension connected to the daemon
+        let tab = client.send_to_extension(ExtensionData::SetVolume { tab_id: 1, volume: 0.5 }).await;
+        assert_eq!(tab, Err(AppError::ExtensionDisconnected));
+
+        // the connection is serving (it answered), so it is subscribed to the daemon's events
+        let daemon_sink: Arc<dyn EventSink> = Arc::new(BroadcastSink(events.clone()));
+        daemon_sink.emit("audio-session-closed", serde_json::json!({ "uid": "a", "reason": "expired" }));
+        let event = tokio::task::spawn_blocking(move || recorder.wait_for("audio-session-closed", Duration::from_secs(2)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.payload["reason"], "expired");
+
+        shutdown.shutdown().await;
+        assert!(shutdown.tasks.is_empty());
+    }
+
+    #[tokio::test]
+    async fn refuses_connections_from_web_pages() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let shutdown = Shutdown::new();
+        let (events, _) = broadcast::channel(16);
+        let (tab_sender, _tab_receiver) = tokio::sync::mpsc::channel(1);
+        let extension = ExtensionCommands::new(tab_sender, ExtensionConnections::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        shutdown.tasks.spawn(control_server(listener, Arc::new(MockBackend::new()), events, extension, shutdown.clone()));
+
+        let mut request = format!("ws://{}", addr).into_client_request().unwrap();
+        request.headers_mut().insert(http::header::ORIGIN, "https://example.com".parse().unwrap());
+        match connect_async(request).await {
+            Err(tokio_tungstenite::tungstenite::Error::Http(response)) => assert_eq!(response.status(), http::StatusCode::FORBIDDEN),
+            other => panic!("expected the handshake to be refused, got {:?}", other.map(|_| ())),
+        }
+
+        // the same request without a browser's Origin goes through
+        assert!(connect_async(format!("ws://{}", addr)).await.is_ok());
+        shutdown.shutdown().await;
+    }
+}
@@ -0,0 +1,167 @@
+// The error every command returns. The frontend gets `{ kind, message, hresult }` and decides on `kind`:
+// a session that is gone is dropped from the list, a missing extension hides the tabs, meters the backend can't read are hidden,
+// the rest is shown as is.
+// The variant's own field (uid, key, pid or device_id) goes along, so a desktop app attached to the daemon gets the same error back.
+use std::fmt;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppError {
+    SessionNotFound { uid: String }, // closed, or the pid no longer owns the uid
+    AppGroupNotFound { key: String },
+    ProcessNotFound { pid: u32 },
+    DeviceUnavailable { device_id: String }, // unplugged, disabled or never known
+    InvalidArgument(String),
+    IconUnavailable(String),
+    BackendError { message: String, hresult: Option<i32> }, // the OS call failed, the HRESULT on Windows
+    ExtensionDisconnected, // no browser extension is connected to the websocket server
+    Unsupported(String), // the backend can't do this at all (peak meters on PulseAudio)
+}
+
+impl AppError {
+    pub fn backend(message: impl Into<String>) -> Self {
+        AppError::BackendError { message: message.into(), hresult: None }
+    }
+
+    // `context` says which call failed, the COM error only has the HRESULT's text
+    #[cfg(windows)]
+    pub fn com(context: impl fmt::Display, error: &windows::core::Error) -> Self {
+        AppError::BackendError { message: format!("{}: {}", context, error.message()), hresult: Some(error.code().0) }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AppError::SessionNotFound { .. } => "SessionNotFound",
+            AppError::AppGroupNotFound { .. } => "AppGroupNotFound",
+            AppError::ProcessNotFound { .. } => "ProcessNotFound",
+            AppError::DeviceUnavailable { .. } => "DeviceUnavailable",
+            AppError::InvalidArgument(_) => "InvalidArgument",
+            AppError::IconUnavailable(_) => "IconUnavailable",
+            AppError::BackendError { .. } => "BackendError",
+            AppError::ExtensionDisconnected => "ExtensionDisconnected",
+            AppError::Unsupported(_) => "Unsupported",
+        }
+    }
+
+    pub fn hresult(&self) -> Option<i32> {
+        match self {
+            AppError::BackendError { hresult, .. } => *hresult,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::SessionNotFound { uid } => write!(f, "Session {} not found", uid),
+            AppError::AppGroupNotFound { key } => write!(f, "App group {} not found", key),
+            AppError::ProcessNotFound { pid } => write!(f, "Process {} not found", pid),
+            AppError::DeviceUnavailable { device_id } => write!(f, "Device {} is not available", device_id),
+            AppError::InvalidArgument(message) | AppError::IconUnavailable(message) | AppError::Unsupported(message) => write!(f, "{}", message),
+            AppError::BackendError { message, hresult: Some(hresult) } => write!(f, "{} (HRESULT 0x{:08X})", message, hresult),
+            AppError::BackendError { message, hresult: None } => write!(f, "{}", message),
+            AppError::ExtensionDisconnected => write!(f, "The browser extension is not connected"),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+// what goes over the wire, `message` is the Display text
+#[derive(Serialize, Deserialize)]
+struct WireError {
+    kind: String,
+    message: String,
+    hresult: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    uid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device_id: Option<String>,
+}
+
+impl Serialize for AppError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut wire = WireError {
+            kind: self.kind().to_string(),
+            message: self.to_string(),
+            hresult: self.hresult(),
+            uid: None,
+            key: None,
+            pid: None,
+            device_id: None,
+        };
+        match self {
+            AppError::SessionNotFound { uid } => wire.uid = Some(uid.clone()),
+            AppError::AppGroupNotFound { key } => wire.key = Some(key.clone()),
+            AppError::ProcessNotFound { pid } => wire.pid = Some(*pid),
+            AppError::DeviceUnavailable { device_id } => wire.device_id = Some(device_id.clone()),
+            _ => {}
+        }
+        wire.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AppError {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let wire = WireError::deserialize(deserializer)?;
+        let missing = |field| serde::de::Error::custom(format!("{} without its {}", wire.kind, field));
+        Ok(match wire.kind.as_str() {
+            "SessionNotFound" => AppError::SessionNotFound { uid: wire.uid.clone().ok_or_else(|| missing("uid"))? },
+            "AppGroupNotFound" => AppError::AppGroupNotFound { key: wire.key.clone().ok_or_else(|| missing("key"))? },
+            "ProcessNotFound" => AppError::ProcessNotFound { pid: wire.pid.ok_or_else(|| missing("pid"))? },
+            "DeviceUnavailable" => AppError::DeviceUnavailable { device_id: wire.device_id.clone().ok_or_else(|| missing("device_id"))? },
+            "InvalidArgument" => AppError::InvalidArgument(wire.message),
+            "IconUnavailable" => AppError::IconUnavailable(wire.message),
+            "ExtensionDisconnected" => AppError::ExtensionDisconnected,
+            "Unsupported" => AppError::Unsupported(wire.message),
+            // Display added the HRESULT to the message, it is kept on its own
+            _ => {
+                let message = match wire.hresult {
+                    Some(hresult) => wire.message.strip_suffix(&format!(" (HRESULT 0x{:08X})", hresult)).unwrap_or(&wire.message).to_string(),
+                    None => wire.message,
+                };
+                AppError::BackendError { message, hresult: wire.hresult }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serializes_the_kind_and_message_for_the_frontend() {
+        let not_found = serde_json::to_value(AppError::SessionNotFound { uid: "a".to_string() }).unwrap();
+        assert_eq!(not_found, serde_json::json!({ "kind": "SessionNotFound", "message": "Session a not found", "hresult": null, "uid": "a" }));
+
+        // AUDCLNT_E_DEVICE_INVALIDATED
+        let com = AppError::BackendError { message: "Failed to SetMute on a".to_string(), hresult: Some(0x88890004u32 as i32) };
+        let com = serde_json::to_value(com).unwrap();
+        assert_eq!(com["kind"], "BackendError");
+        assert_eq!(com["message"], "Failed to SetMute on a (HRESULT 0x88890004)");
+        assert_eq!(com["hresult"], 0x88890004u32 as i32);
+    }
+
+    #[test]
+    fn reads_back_what_it_serialized() {
+        let errors = [
+            AppError::SessionNotFound { uid: "a".to_string() },
+            AppError::ProcessNotFound { pid: 7 },
+            AppError::DeviceUnavailable { device_id: "sink:1".to_string() },
+            AppError::InvalidArgument("Session a has 2 channels, got 1 volumes".to_string()),
+            AppError::BackendError { message: "Failed to SetMute on a".to_string(), hresult: Some(0x88890004u32 as i32) },
+            AppError::ExtensionDisconnected,
+            AppError::Unsupported("Peak meters are not available with PulseAudio".to_string()),
+        ];
+        for error in errors {
+            let json = serde_json::to_value(&error).unwrap();
+            assert_eq!(serde_json::from_value::<AppError>(json).unwrap(), error);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+// Where the audio core sends its events ("audio-session-created", "extension-audio-tabs"...).
+// The monitor thread and the websocket server only know about the EventSink trait, so the same
+// core can feed the Tauri window, another front end through a channel, or a test.
+use std::{
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::{broadcast, mpsc::UnboundedSender};
+
+// one event as the frontend would receive it: the event name and its JSON payload
+#[derive(Debug, Clone, PartialEq, Serialize, serde::Deserialize)]
+pub struct EmittedEvent {
+    pub name: String,
+    pub payload: Value,
+}
+
+pub trait EventSink: Send + Sync {
+    fn emit_value(&self, event: &str, payload: Value);
+}
+
+impl dyn EventSink {
+    // serializes the payload once here so every sink gets plain JSON
+    pub fn emit<T: Serialize>(&self, event: &str, payload: T) {
+        match serde_json::to_value(payload) {
+            Ok(payload) => self.emit_value(event, payload),
+            Err(e) => eprintln!("[EventSink] Failed to serialize payload for '{}': {}", event, e),
+        }
+    }
+}
+
+// forwards events to the Tauri frontend, this is what the desktop app uses
+#[cfg(feature = "desktop")]
+pub struct TauriSink(pub tauri::AppHandle);
+
+#[cfg(feature = "desktop")]
+impl EventSink for TauriSink {
+    fn emit_value(&self, event: &str, payload: Value) {
+        use tauri::Emitter;
+        self.0.emit(event, payload).unwrap_or_else(|e| {
+            eprintln!("[EventSink] Failed to emit '{}': {:?}", event, e);
+        });
+    }
+}
+
+// sends events over a tokio channel for any other consumer (a CLI, an HTTP API...)
+// unbounded so the COM/monitor threads never block on a slow reader
+pub struct ChannelSink(pub UnboundedSender<EmittedEvent>);
+
+impl EventSink for ChannelSink {
+    fn emit_value(&self, event: &str, payload: Value) {
+        if self.0.send(EmittedEvent { name: event.to_string(), payload }).is_err() {
+            eprintln!("[EventSink] Channel receiver dropped, '{}' was not delivered", event);
+        }
+    }
+}
+
+// hands every event to all the receivers subscribed to the channel, the daemon's control connections and its stdout printer
+// a receiver that falls behind by more than the channel holds misses the oldest events, nobody else waits for it
+pub struct BroadcastSink(pub broadcast::Sender<EmittedEvent>);
+
+impl EventSink for BroadcastSink {
+    fn emit_value(&self, event: &str, payload: Value) {
+        // an error only means nobody is subscribed right now
+        let _ = self.0.send(EmittedEvent { name: event.to_string(), payload });
+    }
+}
+
+// keeps every event in memory so tests can assert on them
+#[derive(Default)]
+pub struct RecordingSink {
+    events: Mutex<Vec<EmittedEvent>>,
+    new_event: Condvar,
+}
+
+impl RecordingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> Vec<EmittedEvent> {
+        self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+    }
+
+    pub fn events_named(&self, name: &str) -> Vec<EmittedEvent> {
+        self.events().into_iter().filter(|e| e.name == name).collect()
+    }
+
+    // the monitor emits from its own thread, so tests wait for the event instead of sleeping
+    pub fn wait_for(&self, name: &str, timeout: Duration) -> Option<EmittedEvent> {
+        let deadline = Instant::now() + timeout;
+        let mut events = self.events.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        loop {
+            if let Some(event) = events.iter().find(|e| e.name == name) {
+                return Some(event.clone());
+            }
+            let remaining = deadline.checked_duration_since(Instant::now())?;
+            events = self.new_event.wait_timeout(events, remaining).unwrap_or_else(|poisoned| poisoned.into_inner()).0;
+        }
+    }
+}
+
+impl EventSink for RecordingSink {
+    fn emit_value(&self, event: &str, payload: Value) {
+        self.events
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(EmittedEvent { name: event.to_string(), payload });
+        self.new_event.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn channel_sink_forwards_serialized_events() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let sink: Arc<dyn EventSink> = Arc::new(ChannelSink(sender));
+
+        sink.emit("audio-session-closed", "uid-1");
+
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            EmittedEvent { name: "audio-session-closed".to_string(), payload: Value::from("uid-1") }
+        );
+    }
+
+    #[test]
+    fn recording_sink_keeps_events_in_order() {
+        let recorder = Arc::new(RecordingSink::new());
+        let sink: Arc<dyn EventSink> = recorder.clone();
+
+        sink.emit("first", 1);
+        sink.emit("second", serde_json::json!({ "uid": "a" }));
+        sink.emit("first", 2);
+
+        let names: Vec<String> = recorder.events().into_iter().map(|e| e.name).collect();
+        assert_eq!(names, vec!["first", "second", "first"]);
+        assert_eq!(recorder.events_named("first").len(), 2);
+        assert_eq!(recorder.wait_for("second", Duration::ZERO).unwrap().payload["uid"], "a");
+        assert!(recorder.wait_for("missing", Duration::from_millis(10)).is_none());
+    }
+}
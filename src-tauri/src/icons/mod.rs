@@ -0,0 +1,250 @@
+// Application icons for the session list, served to the UI as PNG by the `get_session_icon` command.
+// A session's icon comes from the icon path the app set (an .ico/.png file or an icon resource in a dll/exe)
+// and otherwise from its executable. Reading them is slow, so every PNG is cached by the executable's path.
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use ico::{IconDir, IconImage};
+use crate::audio_backend::IconSource;
+
+#[cfg(windows)]
+mod shell;
+
+type IconResult = Result<Arc<Vec<u8>>, String>;
+
+struct CachedIcon {
+    icon_path: Option<String>, // what it was loaded from, a session pointing somewhere else loads it again
+    png: IconResult, // failures are cached too, an exe without an icon doesn't get read on every listing
+}
+
+// cloning gives another handle to the same cache, like the Shutdown signal, so it can go into a blocking task
+#[derive(Clone, Default)]
+pub struct IconCache {
+    icons: Arc<Mutex<HashMap<String, CachedIcon>>>,
+}
+
+impl IconCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the PNG of a session's icon, from the cache when the same executable was asked for before
+    pub fn session_icon(&self, source: &IconSource) -> IconResult {
+        let key = source.exe_path.as_ref().or(source.icon_path.as_ref()).ok_or("The session has no icon")?;
+        self.get_or_load(key, source.icon_path.as_deref(), || load_icon(source))
+    }
+
+    fn get_or_load(&self, key: &str, icon_path: Option<&str>, load: impl FnOnce() -> Result<Vec<u8>, String>) -> IconResult {
+        if let Some(cached) = self.lock().get(key).filter(|cached| cached.icon_path.as_deref() == icon_path) {
+            return cached.png.clone();
+        }
+        // loaded without the lock held, two sessions of the same app at once only means it is read twice
+        let png = load().map(Arc::new);
+        self.lock().insert(key.to_string(), CachedIcon { icon_path: icon_path.map(str::to_string), png: png.clone() });
+        png
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedIcon>> {
+        self.icons.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+// the icon path first, when it can't be read the executable still has an icon most of the time
+fn load_icon(source: &IconSource) -> Result<Vec<u8>, String> {
+    let from_icon_path = source.icon_path.as_deref().map(|icon_path| {
+        let (file, index) = parse_icon_path(icon_path);
+        load_icon_file(&file, index)
+    });
+    match (from_icon_path, &source.exe_path) {
+        (Some(Ok(png)), _) => Ok(png),
+        (Some(Err(e)), Some(exe_path)) => {
+            eprintln!("[Icons] Falling back to the executable icon: {}", e);
+            extract_icon(exe_path, 0)
+        }
+        (Some(Err(e)), None) => Err(e),
+        (None, Some(exe_path)) => extract_icon(exe_path, 0),
+        (None, None) => Err("The session has no icon".to_string()),
+    }
+}
+
+fn load_icon_file(file: &str, index: i32) -> Result<Vec<u8>, String> {
+    let extension = Path::new(file).extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("ico") => ico_to_png(&read_file(file)?),
+        Some("png") => read_file(file),
+        _ => extract_icon(file, index), // a resource in a dll or exe
+    }
+}
+
+fn read_file(file: &str) -> Result<Vec<u8>, String> {
+    std::fs::read(file).map_err(|e| format!("Failed to read icon {}: {}", file, e))
+}
+
+// Windows icon paths look like "C:\app\app.ico", "C:\app\app.exe,2" or "@%SystemRoot%\System32\AudioSrv.Dll,-203":
+// an optional '@', environment variables, and after the last comma the icon index (negative is a resource id)
+fn parse_icon_path(icon_path: &str) -> (String, i32) {
+    let icon_path = icon_path.trim().trim_start_matches('@');
+    let (file, index) = match icon_path.rsplit_once(',') {
+        Some((file, index)) => match index.trim().parse() {
+            Ok(index) => (file, index),
+            Err(_) => (icon_path, 0),
+        },
+        None => (icon_path, 0),
+    };
+    (expand_environment_variables(file), index)
+}
+
+// replaces %NAME% with the variable's value, unknown ones are left as they are
+fn expand_environment_variables(path: &str) -> String {
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(start) = rest.find('%') {
+        let Some(length) = rest[start + 1..].find('%') else { break };
+        let name = &rest[start + 1..start + 1 + length];
+        expanded.push_str(&rest[..start]);
+        match std::env::var(name) {
+            Ok(value) if !name.is_empty() => expanded.push_str(&value),
+            _ => expanded.push_str(&rest[start..start + length + 2]),
+        }
+        rest = &rest[start + length + 2..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+// an .ico holds the same picture at several sizes, the biggest one looks best in the list
+pub fn ico_to_png(ico: &[u8]) -> Result<Vec<u8>, String> {
+    let icon_dir = IconDir::read(Cursor::new(ico)).map_err(|e| format!("Not a valid icon file: {}", e))?;
+    let entry = icon_dir
+        .entries()
+        .iter()
+        .max_by_key(|entry| (entry.width(), entry.bits_per_pixel()))
+        .ok_or("The icon file has no images")?;
+    let image = entry.decode().map_err(|e| format!("Failed to decode the icon: {}", e))?;
+    image_to_png(&image)
+}
+
+// GDI hands out pixels as BGRA, and icons made before alpha channels existed have every alpha at 0
+pub fn bgra_to_rgba(mut pixels: Vec<u8>) -> Vec<u8> {
+    let has_alpha = pixels.chunks_exact(4).any(|pixel| pixel[3] != 0);
+    for pixel in pixels.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+        if !has_alpha {
+            pixel[3] = 255;
+        }
+    }
+    pixels
+}
+
+fn image_to_png(image: &IconImage) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    image.write_png(&mut png).map_err(|e| format!("Failed to encode the icon as PNG: {}", e))?;
+    Ok(png)
+}
+
+#[cfg(windows)]
+fn extract_icon(file: &str, index: i32) -> Result<Vec<u8>, String> {
+    let (width, height, bgra) = shell::extract_icon(file, index)?;
+    image_to_png(&IconImage::from_rgba_data(width, height, bgra_to_rgba(bgra)))
+}
+
+// only Windows executables carry their icon, elsewhere the app's icon path is all there is
+#[cfg(not(windows))]
+fn extract_icon(file: &str, _index: i32) -> Result<Vec<u8>, String> {
+    Err(format!("Can't extract an icon from {} on this platform", file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const RED_16: &[u8] = include_bytes!("../../fixtures/icons/red_16.ico");
+    const TWO_SIZES: &[u8] = include_bytes!("../../fixtures/icons/two_sizes.ico");
+
+    fn decode_png(png: &[u8]) -> IconImage {
+        IconImage::read_png(Cursor::new(png)).unwrap()
+    }
+
+    #[test]
+    fn converts_bitmap_icons_to_png() {
+        let image = decode_png(&ico_to_png(RED_16).unwrap());
+
+        assert_eq!((image.width(), image.height()), (16, 16));
+        assert_eq!(&image.rgba_data()[..4], &[255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn picks_the_largest_size() {
+        let image = decode_png(&ico_to_png(TWO_SIZES).unwrap());
+
+        assert_eq!((image.width(), image.height()), (32, 32));
+        assert_eq!(&image.rgba_data()[..4], &[0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn rejects_broken_icon_files() {
+        assert!(ico_to_png(&RED_16[..20]).is_err());
+        assert!(ico_to_png(b"not an icon").is_err());
+    }
+
+    #[test]
+    fn swaps_gdi_pixels_and_fills_missing_alpha() {
+        assert_eq!(bgra_to_rgba(vec![1, 2, 3, 0, 4, 5, 6, 0]), vec![3, 2, 1, 255, 6, 5, 4, 255]);
+        assert_eq!(bgra_to_rgba(vec![1, 2, 3, 0, 4, 5, 6, 7]), vec![3, 2, 1, 0, 6, 5, 4, 7]);
+    }
+
+    #[test]
+    fn splits_icon_paths_into_file_and_index() {
+        std::env::set_var("SCP_TEST_ROOT", "C:\\Windows");
+
+        assert_eq!(parse_icon_path("C:\\app\\app.ico"), ("C:\\app\\app.ico".to_string(), 0));
+        assert_eq!(parse_icon_path("C:\\app\\app.exe,2"), ("C:\\app\\app.exe".to_string(), 2));
+        assert_eq!(
+            parse_icon_path("@%SCP_TEST_ROOT%\\System32\\AudioSrv.Dll,-203"),
+            ("C:\\Windows\\System32\\AudioSrv.Dll".to_string(), -203)
+        );
+        assert_eq!(parse_icon_path("%SCP_TEST_MISSING%\\a, b.ico"), ("%SCP_TEST_MISSING%\\a, b.ico".to_string(), 0));
+    }
+
+    #[test]
+    fn loads_ico_files_from_the_icon_path() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/icons/red_16.ico");
+        let source = IconSource { exe_path: None, icon_path: Some(path.to_string()) };
+
+        let png = IconCache::new().session_icon(&source).unwrap();
+
+        assert_eq!(decode_png(&png).width(), 16);
+        assert!(IconCache::new().session_icon(&IconSource::default()).is_err());
+    }
+
+    #[test]
+    fn caches_by_executable_until_the_icon_path_changes() {
+        let cache = IconCache::new();
+        let loads = AtomicUsize::new(0);
+        let load = || {
+            loads.fetch_add(1, Ordering::SeqCst);
+            ico_to_png(RED_16)
+        };
+
+        let first = cache.get_or_load("C:\\app.exe", None, load).unwrap();
+        let second = cache.get_or_load("C:\\app.exe", None, load).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+
+        cache.get_or_load("C:\\app.exe", Some("C:\\other.ico"), load).unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+
+        // failures are remembered as well
+        let failing = || {
+            loads.fetch_add(1, Ordering::SeqCst);
+            Err("no icon".to_string())
+        };
+        assert!(cache.get_or_load("C:\\none.exe", None, failing).is_err());
+        assert!(cache.get_or_load("C:\\none.exe", None, failing).is_err());
+        assert_eq!(loads.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,72 @@
+// Reads an icon out of an executable or dll with the shell, and its pixels out of GDI.
+use std::{ffi::c_void, mem::size_of};
+use windows::{
+    core::HSTRING,
+    Win32::{
+        Graphics::Gdi::{DeleteObject, GetDC, GetDIBits, GetObjectW, ReleaseDC, BITMAP, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, HBITMAP},
+        UI::{
+            Shell::ExtractIconExW,
+            WindowsAndMessaging::{DestroyIcon, GetIconInfo, HICON, ICONINFO},
+        },
+    },
+};
+
+// the large (32x32) icon at `index`, a negative index is a resource id. returns width, height and BGRA pixels
+pub(super) fn extract_icon(file: &str, index: i32) -> Result<(u32, u32, Vec<u8>), String> {
+    let mut icon = HICON(std::ptr::null_mut());
+    let count = unsafe { ExtractIconExW(&HSTRING::from(file), index, Some(&mut icon as *mut HICON), None, 1) };
+    if count == 0 || icon.is_invalid() {
+        return Err(format!("No icon {} in {}", index, file));
+    }
+    let pixels = icon_pixels(icon);
+    unsafe { let _ = DestroyIcon(icon); }
+    pixels
+}
+
+fn icon_pixels(icon: HICON) -> Result<(u32, u32, Vec<u8>), String> {
+    let mut info = ICONINFO::default();
+    unsafe { GetIconInfo(icon, &mut info) }.map_err(|e| format!("Failed to GetIconInfo: {:?}", e))?;
+    // GetIconInfo makes copies of both bitmaps, they are ours to delete
+    let pixels = bitmap_pixels(info.hbmColor);
+    unsafe {
+        let _ = DeleteObject(info.hbmColor.into());
+        let _ = DeleteObject(info.hbmMask.into());
+    }
+    pixels
+}
+
+fn bitmap_pixels(bitmap: HBITMAP) -> Result<(u32, u32, Vec<u8>), String> {
+    if bitmap.is_invalid() {
+        return Err("Monochrome icons are not supported".to_string());
+    }
+    let mut header = BITMAP::default();
+    if unsafe { GetObjectW(bitmap.into(), size_of::<BITMAP>() as i32, Some(&mut header as *mut BITMAP as *mut c_void)) } == 0 {
+        return Err("Failed to read the icon bitmap".to_string());
+    }
+    let (width, height) = (header.bmWidth, header.bmHeight);
+
+    // asking for 32 bits top-down gives the rows in PNG order whatever the bitmap's own format is
+    let mut info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: width,
+            biHeight: -height,
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    let lines = unsafe {
+        let dc = GetDC(None);
+        let lines = GetDIBits(dc, bitmap, 0, height as u32, Some(pixels.as_mut_ptr() as *mut c_void), &mut info, DIB_RGB_COLORS);
+        ReleaseDC(None, dc);
+        lines
+    };
+    if lines == 0 {
+        return Err("Failed to read the icon pixels".to_string());
+    }
+    Ok((width as u32, height as u32, pixels))
+}
@@ -0,0 +1,281 @@
+// Peak meters for the VU bars next to the sliders ("audio-levels").
+// A thread reads the peak of every session and device from the backend at the rate the subscribers asked for
+// and sends them as one batched event per tick. With no subscriber it sleeps on its channel and reads nothing.
+// A backend that can't read peaks at all gets "audio-levels-unavailable" sent once per subscription, the UI hides the meters.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use crate::audio_backend::{AudioLevels, SharedBackend};
+use crate::error::AppError;
+use crate::event_sink::EventSink;
+
+pub const DEFAULT_LEVEL_RATE_HZ: u32 = 30;
+const MAX_LEVEL_RATE_HZ: u32 = 120; // more than the screen refreshes is wasted work
+const SILENCE: f32 = 0.0001; // peaks at or below this count as not producing sound
+
+enum MeterMessage {
+    Subscribe { id: u64, rate_hz: u32 },
+    Unsubscribe(u64),
+    Shutdown,
+    #[cfg(test)]
+    Tick, // one read, for a meter spawned with manual ticks
+}
+
+// Handle to the metering thread, stored in tauri's manage store like the MonitorHandle.
+pub struct LevelMeter {
+    sender: mpsc::Sender<MeterMessage>,
+    next_id: AtomicU64,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl LevelMeter {
+    // starts the meters (if they weren't running) at `rate_hz` batches a second, the fastest subscriber sets the pace
+    // the id is what unsubscribe takes back
+    pub fn subscribe(&self, rate_hz: u32) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let rate_hz = rate_hz.clamp(1, MAX_LEVEL_RATE_HZ);
+        if self.sender.send(MeterMessage::Subscribe { id, rate_hz }).is_err() {
+            eprintln!("[LevelMeter] Meter thread is not running, subscription {} will get no levels.", id);
+        }
+        id
+    }
+
+    // when the last subscriber leaves the thread stops reading levels
+    pub fn unsubscribe(&self, id: u64) {
+        let _ = self.sender.send(MeterMessage::Unsubscribe(id)); // nothing to stop if the thread already exited
+    }
+
+    #[cfg(test)]
+    fn tick(&self) {
+        let _ = self.sender.send(MeterMessage::Tick);
+    }
+
+    pub fn shutdown(&self) {
+        let _ = self.sender.send(MeterMessage::Shutdown);
+        let thread = self.thread.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+        if let Some(thread) = thread {
+            if thread.join().is_err() {
+                eprintln!("[LevelMeter] Meter thread panicked.");
+            }
+        }
+    }
+}
+
+pub fn spawn_level_meter(event_sink: Arc<dyn EventSink>, backend: SharedBackend) -> LevelMeter {
+    spawn(event_sink, backend, false)
+}
+
+// `manual_ticks`: the meter never ticks on its own, only on `LevelMeter::tick`, so tests don't depend on timing
+fn spawn(event_sink: Arc<dyn EventSink>, backend: SharedBackend, manual_ticks: bool) -> LevelMeter {
+    let (sender, receiver) = mpsc::channel::<MeterMessage>();
+    let thread = thread::spawn(move || level_meter_loop(event_sink, backend, receiver, manual_ticks));
+    LevelMeter { sender, next_id: AtomicU64::new(1), thread: Mutex::new(Some(thread)) }
+}
+
+fn level_meter_loop(event_sink: Arc<dyn EventSink>, backend: SharedBackend, receiver: mpsc::Receiver<MeterMessage>, manual_ticks: bool) {
+    let mut subscribers: HashMap<u64, u32> = HashMap::new(); // id -> requested rate
+    let mut audible = Audible::default();
+    let mut next_tick = Instant::now();
+    let mut last_error: Option<AppError> = None;
+
+    loop {
+        // no subscriber: block until one shows up, otherwise wait for a message or the next tick, whichever comes first
+        let rate_hz = subscribers.values().max().filter(|_| !manual_ticks);
+        let message = match rate_hz {
+            None => match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            },
+            Some(&rate_hz) => match receiver.recv_timeout(next_tick.saturating_duration_since(Instant::now())) {
+                Ok(message) => Some(message),
+                Err(RecvTimeoutError::Timeout) => {
+                    // a slow read shouldn't make ticks pile up, so never schedule the next one in the past
+                    next_tick = (next_tick + Duration::from_secs_f64(1.0 / rate_hz as f64)).max(Instant::now());
+                    None
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+        };
+
+        match message {
+            Some(MeterMessage::Subscribe { id, rate_hz }) => {
+                if subscribers.is_empty() {
+                    next_tick = Instant::now();
+                }
+                subscribers.insert(id, rate_hz);
+            }
+            Some(MeterMessage::Unsubscribe(id)) => {
+                subscribers.remove(&id);
+                if subscribers.is_empty() {
+                    // the next subscriber starts from a clean slate, and hears about an unsupported backend again
+                    audible = Audible::default();
+                    last_error = None;
+                }
+            }
+            Some(MeterMessage::Shutdown) => break,
+            #[cfg(test)]
+            Some(MeterMessage::Tick) if subscribers.is_empty() => {}
+            #[cfg(test)]
+            Some(MeterMessage::Tick) => read_levels(&event_sink, &backend, &mut audible, &mut last_error),
+            None => read_levels(&event_sink, &backend, &mut audible, &mut last_error),
+        }
+    }
+}
+
+// one tick: reads every peak and sends what is producing sound
+fn read_levels(event_sink: &Arc<dyn EventSink>, backend: &SharedBackend, audible: &mut Audible, last_error: &mut Option<AppError>) {
+    match backend.read_levels() {
+        Ok(levels) => {
+            *last_error = None;
+            if let Some(batch) = audible.filter(levels) {
+                event_sink.emit("audio-levels", batch);
+            }
+        }
+        Err(e) => {
+            // the same failure would be printed every tick, once is enough
+            if last_error.as_ref() != Some(&e) {
+                eprintln!("[LevelMeter] Failed to read audio levels: {}", e);
+                if matches!(e, AppError::Unsupported(_)) {
+                    event_sink.emit("audio-levels-unavailable", &e);
+                }
+                *last_error = Some(e);
+            }
+        }
+    }
+}
+
+// what was in the previous batch, so something that just went quiet gets one last 0.0 and its meter falls back
+#[derive(Default)]
+struct Audible {
+    sessions: HashSet<String>,
+    devices: HashSet<String>,
+}
+
+impl Audible {
+    // keeps only what is producing sound (plus what just stopped), None when there is nothing to send
+    fn filter(&mut self, levels: AudioLevels) -> Option<AudioLevels> {
+        let batch = AudioLevels {
+            sessions: keep_audible(levels.sessions, |level| (&level.session_uid, &mut level.peak), &mut self.sessions),
+            devices: keep_audible(levels.devices, |level| (&level.device_id, &mut level.peak), &mut self.devices),
+        };
+        (!batch.sessions.is_empty() || !batch.devices.is_empty()).then_some(batch)
+    }
+}
+
+fn keep_audible<T>(levels: Vec<T>, key_and_peak: impl Fn(&mut T) -> (&String, &mut f32), audible: &mut HashSet<String>) -> Vec<T> {
+    let mut now_audible = HashSet::new();
+    let kept = levels
+        .into_iter()
+        .filter_map(|mut level| {
+            let (key, peak) = key_and_peak(&mut level);
+            if *peak > SILENCE {
+                now_audible.insert(key.clone());
+            } else if audible.contains(key) {
+                *peak = 0.0;
+            } else {
+                return None;
+            }
+            Some(level)
+        })
+        .collect();
+    *audible = now_audible;
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_backend::{mock::MockBackend, AudioBackend, DeviceLevel, SessionLevel};
+    use crate::event_sink::RecordingSink;
+
+    const FIXTURE: &str = include_str!("../fixtures/mock_sessions.json");
+
+    fn levels(sessions: &[(&str, f32)], devices: &[(&str, f32)]) -> AudioLevels {
+        AudioLevels {
+            sessions: sessions.iter().map(|&(uid, peak)| SessionLevel { session_uid: uid.to_string(), peak }).collect(),
+            devices: devices.iter().map(|&(id, peak)| DeviceLevel { device_id: id.to_string(), peak }).collect(),
+        }
+    }
+
+    #[test]
+    fn batches_only_sessions_producing_sound_and_one_last_zero() {
+        let mut audible = Audible::default();
+
+        assert_eq!(audible.filter(levels(&[("a", 0.0), ("b", 0.0)], &[("speakers", 0.0)])), None);
+        assert_eq!(
+            audible.filter(levels(&[("a", 0.5), ("b", 0.00001)], &[("speakers", 0.5)])),
+            Some(levels(&[("a", 0.5)], &[("speakers", 0.5)]))
+        );
+        // "a" went quiet: it gets a 0.0 once so the bar drops, then it is left out
+        assert_eq!(
+            audible.filter(levels(&[("a", 0.0), ("b", 0.2)], &[("speakers", 0.0)])),
+            Some(levels(&[("a", 0.0), ("b", 0.2)], &[("speakers", 0.0)]))
+        );
+        assert_eq!(audible.filter(levels(&[("a", 0.0), ("b", 0.2)], &[("speakers", 0.0)])), Some(levels(&[("b", 0.2)], &[])));
+    }
+
+    #[test]
+    fn emits_levels_only_while_subscribed() {
+        let backend = MockBackend::from_json(FIXTURE).unwrap();
+        let uid = backend.list_sessions().unwrap()[0].session_uid.clone();
+        backend.set_peak(&uid, 0.8);
+        let recorder = Arc::new(RecordingSink::new());
+        let meter = spawn(recorder.clone(), Arc::new(backend.clone()), true);
+
+        // the thread handles the messages in order, so every tick lands before or after the (un)subscribe
+        meter.tick();
+        let id = meter.subscribe(MAX_LEVEL_RATE_HZ);
+        meter.tick();
+        meter.unsubscribe(id);
+        meter.tick();
+        meter.shutdown(); // joins, everything above has been handled
+
+        let batches = recorder.events_named("audio-levels");
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].payload["sessions"], serde_json::json!([{ "uid": uid, "peak": 0.8f32 }]));
+        assert_eq!(batches[0].payload["devices"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn tells_each_subscription_once_when_the_backend_has_no_meters() {
+        let backend = MockBackend::from_json(FIXTURE).unwrap();
+        backend.fail_levels(AppError::Unsupported("no meters".to_string()));
+        let recorder = Arc::new(RecordingSink::new());
+        let meter = spawn(recorder.clone(), Arc::new(backend), true);
+
+        let id = meter.subscribe(MAX_LEVEL_RATE_HZ);
+        meter.tick();
+        meter.tick();
+        meter.unsubscribe(id);
+        meter.subscribe(MAX_LEVEL_RATE_HZ);
+        meter.tick();
+        meter.shutdown();
+
+        let unavailable = recorder.events_named("audio-levels-unavailable");
+        assert_eq!(unavailable.len(), 2);
+        assert_eq!(unavailable[0].payload["kind"], "Unsupported");
+        assert!(recorder.events_named("audio-levels").is_empty());
+    }
+
+    #[test]
+    fn ticks_on_its_own_at_the_subscribed_rate() {
+        let backend = MockBackend::from_json(FIXTURE).unwrap();
+        let uid = backend.list_sessions().unwrap()[0].session_uid.clone();
+        backend.set_peak(&uid, 0.8);
+        let recorder = Arc::new(RecordingSink::new());
+        let meter = spawn_level_meter(recorder.clone(), Arc::new(backend));
+
+        meter.subscribe(MAX_LEVEL_RATE_HZ);
+        let batch = recorder.wait_for("audio-levels", Duration::from_secs(2)).unwrap();
+        assert_eq!(batch.payload["sessions"], serde_json::json!([{ "uid": uid, "peak": 0.8f32 }]));
+
+        meter.shutdown();
+    }
+}
@@ -3,16 +3,31 @@
     all(not(debug_assertions), target_os = "windows"),
     windows_subsystem = "windows"
 )]
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+#[cfg(feature = "desktop")]
+use std::sync::Arc;
+#[cfg(feature = "desktop")]
 use tokio::sync::mpsc;
+#[cfg(feature = "desktop")]
+use audio_backend::SharedBackend;
+#[cfg(feature = "desktop")]
 use tauri::{Manager, WindowEvent}; // Manager is needed for app.manage() and state()
 
+#[cfg(feature = "desktop")]
 mod commands;
-mod audio_monitor;
+pub mod app_groups;
+pub mod audio_monitor;
+pub mod audio_backend;
+pub mod control;
+pub mod error;
+pub mod event_sink;
+pub mod icons;
+pub mod level_meter;
+pub mod process_info;
 
-#[derive(Debug, Clone, serde::Serialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 // enum to represent the type of data we are sending through the mpsc channel and finally to the browser extension
+// (Deserialize is for the daemon, an attached desktop app sends its tab commands in the same shape)
 pub enum ExtensionData {
     
     SetVolume { 
@@ -31,48 +46,72 @@ pub enum ExtensionData {
     },
 }
 
+#[cfg(feature = "desktop")]
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     tauri::Builder::default()
         .setup(|app| {
-            // 1. Get an AppHandle. This is a controller for the app that is safe to
-            //    send to other threads. We'll pass this to our monitor thread so it
+            // 1. Wrap an AppHandle in an EventSink. The AppHandle is a controller for the app that is safe to
+            //    send to other threads. We'll pass the sink to our monitor thread so it
             //    can send events to the frontend.
-            let monitor_app_handle = app.handle().clone(); // for the monitor_thread_loop
-            let websocket_app_handle = app.handle().clone(); // for the websocket_server
+            let event_sink: Arc<dyn event_sink::EventSink> = Arc::new(event_sink::TauriSink(app.handle().clone()));
 
-            // 2. Create the shared shutdown signal.
-            //    This is an Arc<AtomicBool> that both the main app and the monitor thread
-            //    will have access to. It starts as `false`.
-            let shutdown_flag = Arc::new(AtomicBool::new(false));
-            // channel to send values to websocket server and to the Extension, 
-            // sender is given to command functions in lib.rs and reciever given to the websocket server function in audio_monitor.rs
-            let (tab_data_sender, mut tab_data_receiver) = mpsc::channel::<ExtensionData>(128); // 128 is the buffer size
+            // 2. Create the shared shutdown signal for the async tasks.
+            //    Its cancellation token is awaited by the websocket server and every connection,
+            //    its task tracker lets the close handler wait for them to finish.
+            let shutdown = audio_monitor::Shutdown::new();
 
             // 3. Store the shutdown signal in Tauri's managed state.
             //    This allows us to retrieve it later in the `on_window_event` handler
             //    when the user tries to close the window.
-            // The `manage` method makes `Arc<AtomicBool>` available throughout the app.
-            app.manage(shutdown_flag.clone());// here we are creating a new Arc pointer that points to the exact same AtomicBool on the heap, we are not cloning atomicbool itself
+            // The `manage` method makes `Shutdown` available throughout the app.
+            app.manage(shutdown.clone());// the clone shares the same token and tracker, cancelling one cancels both
             // any part of the application that has access to an AppHandle or a Window object can now retrieve this shared state, or injected into Tauri commands using the `State` parameter
-            app.manage(tab_data_sender); // store the sender to access it from the command functions parameters with 'state'
-
-            let monitor_thread_signal = shutdown_flag.clone(); // clone the shutdown arc to give it to the monitor thread
-            // 4. Spawn the dedicated background thread for audio monitoring.
-            //    `std::thread::spawn` starts a new OS thread.
-            //    The `move` keyword gives the closure ownership of the variables it uses
-            //    (app_handle and shutdown_flag).
-            std::thread::spawn(move || {
-                // The new thread will execute this function from our audio_monitor module.
-                // the params are app_handle for communicating with the frontend and shutdown_flag for graceful termination
-                audio_monitor::monitor_thread_loop(monitor_app_handle, monitor_thread_signal);
-            });
-            // --- End of the Audio Monitor Setup ---
+            app.manage(icons::IconCache::new()); // PNGs of the session icons, for get_session_icon
+
+            // A running daemon already owns the audio backend and the extension's port, so the app attaches to it:
+            // the commands go to the daemon and its events come back through the same sink the monitor would use.
+            let daemon_addr = control::control_addr();
+            let attached = tauri::async_runtime::block_on(control::ControlClient::connect(&daemon_addr, event_sink.clone(), shutdown.clone()));
+            let backend: SharedBackend = match attached {
+                Ok(client) => {
+                    println!("[Tauri] Attached to the daemon on {}.", daemon_addr);
+                    app.manage(control::ExtensionLink::Daemon(client.clone())); // the tab commands go through the daemon too
+                    Arc::new(client)
+                }
+                Err(_) => {
+                    // no daemon, the app runs everything itself
+                    // The platform audio backend (WASAPI on Windows). Both the commands and the monitor thread only use it through the AudioBackend trait.
+                    let backend = audio_backend::default_backend();
+
+                    // channel to send values to websocket server and to the Extension,
+                    // sender is given to the tab commands through the ExtensionLink and reciever given to the websocket server function in audio_monitor.rs
+                    let (tab_data_sender, tab_data_receiver) = mpsc::channel::<ExtensionData>(128); // 128 is the buffer size
+                    let extension_connections = audio_monitor::ExtensionConnections::new(); // the tab commands check it before sending
+                    let extension = audio_monitor::ExtensionCommands::new(tab_data_sender, extension_connections.clone());
+                    app.manage(control::ExtensionLink::Local(extension));
 
-            // spawn a tokio non blocking task to handle the websocket server that listens to audio updates from the browser extension
-            // No need for a clone of the shutdown_flag if this is the last use of `shutdown_flag` in `setup`
-            // 'tab_data_receiver' is passed to it for mpsc communications to receive data from sender in command functions in lib.rs
-            tauri::async_runtime::spawn(audio_monitor::websocket_server(websocket_app_handle, shutdown_flag, tab_data_receiver));
+                    // 4. Spawn the dedicated background thread for audio monitoring.
+                    //    The thread sleeps until the backend reports a session event, the handle we get back
+                    //    is stored in the manage store so closing the window can stop it with a Shutdown message.
+                    let monitor = audio_monitor::spawn_monitor(event_sink.clone(), backend.clone());
+                    app.manage(monitor);
+
+                    // spawn a tokio non blocking task to handle the websocket server that listens to audio updates from the browser extension
+                    // the server is tracked like its connections so closing the window waits for it too
+                    // 'tab_data_receiver' is passed to it for mpsc communications to receive data from the tab commands
+                    let server = shutdown.tasks.track_future(audio_monitor::websocket_server(audio_monitor::extension_addr(), event_sink.clone(), shutdown.clone(), tab_data_receiver, extension_connections));
+                    tauri::async_runtime::spawn(server);
+                    backend
+                }
+            };
+            app.manage(backend.clone()); // the audio commands get the backend from the store with 'state'
+
+            // the VU meters, their thread idles until the window subscribes to "audio-levels"
+            // attached, it polls the daemon's levels
+            let level_meter = level_meter::spawn_level_meter(event_sink, backend);
+            app.manage(level_meter);
+            // --- End of the Audio Monitor Setup ---
 
             // The setup hook must return Ok(()) to indicate success.
             Ok(())
@@ -80,21 +119,45 @@ pub fn run() {
         // --- WINDOW EVENT HOOK: This listens for events on all windows ---
         .on_window_event(|window, event| match event {
             // Listen for the user trying to close a window.
-            WindowEvent::CloseRequested { .. } => {
+            WindowEvent::CloseRequested { api, .. } => {
 
                 // Retrieve the shutdown signal from Tauri's managed state.
-                if let Some(shutdown_flag) = window.try_state::<Arc<AtomicBool>>() {
-                    // Atomically set the flag to `true`.
-                    // The monitor thread's loop will see this change and break.
-                    shutdown_flag.store(true, Ordering::Relaxed);
-
-                    // Optional: Give the monitor thread a moment to run its cleanup code
-                    // (unregistering listeners, CoUninitialize). This can help prevent
-                    // abrupt termination issues. 500ms is usually more than enough.
-                    //std::thread::sleep(std::time::Duration::from_millis(500));
-                } else {
-                    eprintln!("[Tauri] Could not get shutdown_flag from state to signal monitor thread.");
+                let Some(shutdown) = window.try_state::<audio_monitor::Shutdown>() else {
+                    eprintln!("[Tauri] Could not get the shutdown signal from state, closing without a clean shutdown.");
+                    return;
+                };
+                let shutdown = shutdown.inner().clone();
+
+                // Keep the window open until the websocket tasks have sent their close frames,
+                // the app exits once they are done.
+                api.prevent_close();
+                if shutdown.token.is_cancelled() {
+                    return; // already shutting down
+                }
+
+                // The monitor thread wakes up on the Shutdown message, drops its subscription
+                // (unregistering the listeners) and exits, shutdown() waits for that.
+                // (an app attached to the daemon has no monitor of its own)
+                if let Some(monitor) = window.try_state::<audio_monitor::MonitorHandle>() {
+                    monitor.shutdown();
                 }
+
+                if let Some(level_meter) = window.try_state::<level_meter::LevelMeter>() {
+                    level_meter.shutdown();
+                }
+
+                // nothing uses the backend anymore, its COM worker is stopped and joined here instead of whenever
+                // the last clone happens to be dropped (attached to the daemon, this does nothing)
+                let backend = window.try_state::<SharedBackend>().map(|backend| backend.inner().clone());
+
+                let app_handle = window.app_handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    shutdown.shutdown().await;
+                    if let Some(backend) = backend {
+                        let _ = tauri::async_runtime::spawn_blocking(move || backend.shutdown()).await;
+                    }
+                    app_handle.exit(0);
+                });
             }
             _ => {} // Ignore all other window events.
         })
@@ -103,6 +166,20 @@ pub fn run() {
             commands::audio::set_volume, 
             commands::audio::set_mute, 
             commands::audio::get_sessions_and_volumes,
+            commands::audio::list_devices,
+            commands::audio::get_app_groups,
+            commands::audio::set_group_volume,
+            commands::audio::set_group_mute,
+            commands::audio::get_channel_volumes,
+            commands::audio::set_channel_volumes,
+            commands::audio::set_balance,
+            commands::audio::get_master_volume,
+            commands::audio::set_master_volume,
+            commands::audio::set_master_mute,
+            commands::audio::get_session_icon,
+            commands::audio::get_process_info,
+            commands::audio::subscribe_audio_levels,
+            commands::audio::unsubscribe_audio_levels,
             commands::audio::set_tab_volume,
             commands::audio::set_tab_mute,])
         .run(tauri::generate_context!())
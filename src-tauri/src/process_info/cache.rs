@@ -0,0 +1,171 @@
+// The process table is only read again when a lookup misses, and every process that was looked up
+// keeps its identity. A hit costs one start-time query (to notice the pid went to another process) and a map lookup.
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use super::{top_level_ancestor, AppIdentity, ProcessIdentity, ProcessTable};
+
+// a pid that can't be opened and isn't in the table doesn't take a new snapshot on every lookup
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
+// where the cache gets the processes from, the system's or a fixed table in the tests
+pub(super) trait ProcessSource: Send {
+    fn snapshot(&self) -> ProcessTable;
+    fn start_time(&self, pid: u32) -> Option<u64>;
+    fn image_path(&self, pid: u32) -> Option<String>;
+}
+
+struct CachedIdentity {
+    start_time: Option<u64>, // None when the process can't be opened, the pid is then trusted as it is
+    identity: ProcessIdentity,
+}
+
+pub(super) struct ProcessCache {
+    source: Box<dyn ProcessSource>,
+    processes: ProcessTable,
+    refreshed_at: Option<Instant>,
+    identities: HashMap<u32, CachedIdentity>,
+}
+
+impl ProcessCache {
+    pub(super) fn new(source: Box<dyn ProcessSource>) -> Self {
+        Self { source, processes: ProcessTable::new(), refreshed_at: None, identities: HashMap::new() }
+    }
+
+    pub(super) fn identity(&mut self, pid: u32) -> Option<ProcessIdentity> {
+        let start_time = self.source.start_time(pid);
+        let reused = match self.identities.get(&pid) {
+            Some(cached) if cached.start_time == start_time => return Some(cached.identity.clone()),
+            Some(_) => true,
+            None => false,
+        };
+        // a reused pid always refreshes, the table still has the old process's parent,
+        // and so does a missing one that is running (it has a start time), it started after the last snapshot
+        let missing = !self.processes.contains_key(&pid);
+        if reused || (missing && (start_time.is_some() || !self.refreshed_recently())) {
+            self.refresh();
+        }
+
+        let identity = self.identify(pid)?;
+        self.identities.insert(pid, CachedIdentity { start_time, identity: identity.clone() });
+        Some(identity)
+    }
+
+    fn refreshed_recently(&self) -> bool {
+        self.refreshed_at.is_some_and(|at| at.elapsed() < MIN_REFRESH_INTERVAL)
+    }
+
+    // the identities of processes that exited go with the old table
+    fn refresh(&mut self) {
+        self.processes = self.source.snapshot();
+        self.refreshed_at = Some(Instant::now());
+        let processes = &self.processes;
+        self.identities.retain(|pid, _| processes.contains_key(pid));
+    }
+
+    fn identify(&self, pid: u32) -> Option<ProcessIdentity> {
+        let mut process = self.processes.get(&pid)?.clone();
+        process.image_path = self.source.image_path(pid);
+        let app = top_level_ancestor(pid, &self.processes, |pid| self.source.start_time(pid), |pid| self.source.image_path(pid))?;
+        let app = AppIdentity {
+            pid: app.pid,
+            exe_name: app.exe_name.clone(),
+            image_path: if app.pid == pid { process.image_path.clone() } else { self.source.image_path(app.pid) },
+        };
+        Some(ProcessIdentity { process, app })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::process_info::tests::table;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct FakeState {
+        processes: ProcessTable,
+        start_times: HashMap<u32, u64>,
+        snapshots: usize,
+    }
+
+    #[derive(Clone, Default)]
+    struct FakeProcesses(Arc<Mutex<FakeState>>);
+
+    impl ProcessSource for FakeProcesses {
+        fn snapshot(&self) -> ProcessTable {
+            let mut state = self.0.lock().unwrap();
+            state.snapshots += 1;
+            state.processes.clone()
+        }
+
+        fn start_time(&self, pid: u32) -> Option<u64> {
+            self.0.lock().unwrap().start_times.get(&pid).copied()
+        }
+
+        fn image_path(&self, pid: u32) -> Option<String> {
+            let state = self.0.lock().unwrap();
+            state.processes.get(&pid).map(|process| format!("C:\\apps\\{}", process.exe_name))
+        }
+    }
+
+    fn fake(processes: &[(u32, u32, &str)]) -> FakeProcesses {
+        let source = FakeProcesses::default();
+        let mut state = source.0.lock().unwrap();
+        state.processes = table(processes);
+        state.start_times = state.processes.keys().map(|&pid| (pid, 100)).collect();
+        drop(state);
+        source
+    }
+
+    #[test]
+    fn repeated_lookups_reuse_one_snapshot() {
+        let source = fake(&[(700, 4, "explorer.exe"), (1000, 700, "Discord.exe"), (1010, 1000, "Discord.exe")]);
+        let mut cache = ProcessCache::new(Box::new(source.clone()));
+
+        for _ in 0..30 {
+            let identity = cache.identity(1010).unwrap();
+            assert_eq!(identity.app.pid, 1000);
+            assert_eq!(identity.app.image_path.as_deref(), Some("C:\\apps\\Discord.exe"));
+        }
+        cache.identity(1000).unwrap();
+
+        assert_eq!(source.0.lock().unwrap().snapshots, 1);
+    }
+
+    #[test]
+    fn a_reused_pid_is_looked_up_again() {
+        let source = fake(&[(700, 4, "explorer.exe"), (1000, 700, "game.exe")]);
+        let mut cache = ProcessCache::new(Box::new(source.clone()));
+        assert_eq!(cache.identity(1000).unwrap().process.exe_name, "game.exe");
+
+        // game.exe exited and its pid went to a new process
+        {
+            let mut state = source.0.lock().unwrap();
+            state.processes = table(&[(700, 4, "explorer.exe"), (1000, 700, "Spotify.exe")]);
+            state.start_times.insert(1000, 200);
+        }
+
+        assert_eq!(cache.identity(1000).unwrap().process.exe_name, "Spotify.exe");
+        assert_eq!(source.0.lock().unwrap().snapshots, 2);
+    }
+
+    #[test]
+    fn missing_pids_refresh_at_most_once_per_interval() {
+        let source = fake(&[(700, 4, "explorer.exe")]);
+        let mut cache = ProcessCache::new(Box::new(source.clone()));
+
+        assert!(cache.identity(4242).is_none());
+        assert!(cache.identity(4242).is_none());
+        assert_eq!(source.0.lock().unwrap().snapshots, 1);
+
+        // a process that started since then is found right away
+        {
+            let mut state = source.0.lock().unwrap();
+            state.processes = table(&[(700, 4, "explorer.exe"), (4343, 700, "new.exe")]);
+            state.start_times.insert(4343, 300);
+        }
+        assert_eq!(cache.identity(4343).unwrap().process.exe_name, "new.exe");
+    }
+}
@@ -0,0 +1,265 @@
+// Who a process is: its parent, the full path of its executable and the application it belongs to.
+// Audio often comes from a helper (a browser renderer, "steamwebhelper", an Electron utility process),
+// walking up the process tree to the parents the helper is part of gives the app the user actually started.
+// Only a parent installed in the same directory (or a known helper's parent) counts, a game that Steam started is its own app.
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, Mutex, MutexGuard},
+};
+
+mod cache;
+use cache::{ProcessCache, ProcessSource};
+
+#[cfg(windows)]
+mod toolhelp;
+#[cfg(windows)]
+use toolhelp as platform;
+#[cfg(target_os = "linux")]
+mod procfs;
+#[cfg(target_os = "linux")]
+use procfs as platform;
+
+// one entry of the process table, the image path is only read for the processes that are looked up
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub parent_pid: Option<u32>, // None for the roots (System, init) and when the parent is unknown
+    pub exe_name: String,
+    pub image_path: Option<String>, // None when the process can't be opened (another user's, protected)
+}
+
+// the application a process belongs to, the ancestor it is part of ("Discord.exe" for a Discord helper)
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct AppIdentity {
+    pub pid: u32,
+    pub exe_name: String,
+    pub image_path: Option<String>,
+}
+
+// what get_process_info sends: the process itself and the app it was attributed to
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ProcessIdentity {
+    #[serde(flatten)]
+    pub process: ProcessInfo,
+    pub app: AppIdentity,
+}
+
+type ProcessTable = HashMap<u32, ProcessInfo>;
+
+// processes that start apps without being part of them, the walk up the tree stops below them
+const LAUNCHERS: &[&str] = &[
+    "system", "smss.exe", "csrss.exe", "wininit.exe", "winlogon.exe", "services.exe", "svchost.exe", "userinit.exe",
+    "explorer.exe", "sihost.exe", "runtimebroker.exe", "cmd.exe", "powershell.exe", "pwsh.exe", "conhost.exe",
+    "windowsterminal.exe", "openconsole.exe", "init", "systemd", "sh", "bash", "zsh", "fish", "gnome-shell", "plasmashell",
+    "kwin_wayland", "sway",
+];
+
+// helpers installed away from the app that starts them ("steamwebhelper.exe" lives under Steam\bin\cef)
+const HELPERS: &[&str] = &[
+    "steamwebhelper.exe", "steamwebhelper", "msedgewebview2.exe", "cefsharp.browsersubprocess.exe", "qtwebengineprocess.exe",
+];
+
+fn is_launcher(exe_name: &str) -> bool {
+    LAUNCHERS.contains(&exe_name.to_lowercase().as_str())
+}
+
+fn is_helper(exe_name: &str) -> bool {
+    HELPERS.contains(&exe_name.to_lowercase().as_str())
+}
+
+// the directory of an image path, lowercased since Windows paths aren't case-sensitive
+fn install_dir(image_path: &str) -> String {
+    image_path.rsplit_once(['\\', '/']).map_or("", |(dir, _)| dir).to_lowercase()
+}
+
+// whether the child is part of the parent's app: installed next to it, or a known helper
+// without both image paths (another user's process) only the same executable counts
+fn part_of_parent(child: &ProcessInfo, parent: &ProcessInfo, image_path: &impl Fn(u32) -> Option<String>) -> bool {
+    if is_helper(&child.exe_name) {
+        return true;
+    }
+    match (image_path(child.pid), image_path(parent.pid)) {
+        (Some(child_path), Some(parent_path)) => install_dir(&child_path) == install_dir(&parent_path),
+        _ => child.exe_name.eq_ignore_ascii_case(&parent.exe_name),
+    }
+}
+
+// the highest ancestor the process is part of, the walk stops at the first parent that is a launcher or another app
+// `start_time` tells a real parent from a process that got the parent's pid after it exited: that one started later
+fn top_level_ancestor(
+    pid: u32,
+    processes: &ProcessTable,
+    start_time: impl Fn(u32) -> Option<u64>,
+    image_path: impl Fn(u32) -> Option<String>,
+) -> Option<&ProcessInfo> {
+    let mut current = processes.get(&pid)?;
+    let mut current_start = start_time(pid);
+    let mut visited = HashSet::from([pid]); // a reused pid can make the parent links loop
+    while let Some(parent) = current.parent_pid.and_then(|parent_pid| processes.get(&parent_pid)) {
+        if is_launcher(&parent.exe_name) || !visited.insert(parent.pid) || !part_of_parent(current, parent, &image_path) {
+            break;
+        }
+        let parent_start = start_time(parent.pid);
+        if let (Some(parent_start), Some(child_start)) = (parent_start, current_start) {
+            if parent_start > child_start {
+                break;
+            }
+        }
+        current = parent;
+        current_start = parent_start;
+    }
+    Some(current)
+}
+
+static CACHE: LazyLock<Mutex<ProcessCache>> = LazyLock::new(|| Mutex::new(ProcessCache::new(Box::new(SystemProcesses))));
+
+fn cache() -> MutexGuard<'static, ProcessCache> {
+    CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// the process, its parent and the app it belongs to, None when the pid isn't running
+pub fn process_identity(pid: u32) -> Option<ProcessIdentity> {
+    cache().identity(pid)
+}
+
+pub fn app_identity(pid: u32) -> Option<AppIdentity> {
+    process_identity(pid).map(|identity| identity.app)
+}
+
+// the full path of the process's executable
+pub fn image_path(pid: u32) -> Option<String> {
+    platform::image_path(pid)
+}
+
+// the processes of the running system, what the shared cache reads
+struct SystemProcesses;
+
+impl ProcessSource for SystemProcesses {
+    fn snapshot(&self) -> ProcessTable {
+        platform::snapshot()
+    }
+
+    fn start_time(&self, pid: u32) -> Option<u64> {
+        platform::start_time(pid)
+    }
+
+    fn image_path(&self, pid: u32) -> Option<String> {
+        platform::image_path(pid)
+    }
+}
+
+// no process table on other platforms, every lookup finds nothing
+#[cfg(not(any(windows, target_os = "linux")))]
+mod platform {
+    use super::ProcessTable;
+
+    pub(super) fn snapshot() -> ProcessTable {
+        ProcessTable::new()
+    }
+
+    pub(super) fn image_path(_pid: u32) -> Option<String> {
+        None
+    }
+
+    pub(super) fn start_time(_pid: u32) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn no_start_time(_pid: u32) -> Option<u64> {
+        None
+    }
+
+    fn no_image_path(_pid: u32) -> Option<String> {
+        None
+    }
+
+    pub(super) fn table(processes: &[(u32, u32, &str)]) -> ProcessTable {
+        processes
+            .iter()
+            .map(|&(pid, parent_pid, exe_name)| {
+                (pid, ProcessInfo { pid, parent_pid: Some(parent_pid), exe_name: exe_name.to_string(), image_path: None })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn helpers_belong_to_the_app_that_started_them() {
+        let processes = table(&[
+            (4, 0, "System"),
+            (700, 4, "explorer.exe"),
+            (1000, 700, "Discord.exe"),
+            (1010, 1000, "Discord.exe"),
+            (1020, 1010, "Discord.exe"),
+            (2000, 700, "steam.exe"),
+            (2010, 2000, "steamwebhelper.exe"),
+            (3000, 700, "game.exe"),
+        ]);
+
+        assert_eq!(top_level_ancestor(1020, &processes, no_start_time, no_image_path).unwrap().pid, 1000);
+        assert_eq!(top_level_ancestor(2010, &processes, no_start_time, no_image_path).unwrap().exe_name, "steam.exe");
+        assert_eq!(top_level_ancestor(3000, &processes, no_start_time, no_image_path).unwrap().pid, 3000);
+        assert_eq!(top_level_ancestor(700, &processes, no_start_time, no_image_path).unwrap().pid, 700);
+        assert!(top_level_ancestor(9999, &processes, no_start_time, no_image_path).is_none());
+    }
+
+    #[test]
+    fn apps_started_by_a_launcher_app_are_their_own() {
+        let processes = table(&[
+            (700, 4, "explorer.exe"),
+            (2000, 700, "steam.exe"),
+            (2010, 2000, "steamwebhelper.exe"),
+            (2020, 2000, "game.exe"),
+            (2030, 2020, "game.exe"),
+            (2040, 2020, "UnityCrashHandler64.exe"),
+            (2050, 2000, "unreadable.exe"),
+        ]);
+        let image_path = |pid| {
+            let path = match pid {
+                2000 => "C:\\Steam\\steam.exe",
+                2010 => "C:\\Steam\\bin\\cef\\cef.win7x64\\steamwebhelper.exe",
+                2020 | 2030 => "C:\\Steam\\steamapps\\common\\Game\\game.exe",
+                2040 => "C:\\Steam\\steamapps\\common\\Game\\UnityCrashHandler64.exe",
+                _ => return None,
+            };
+            Some(path.to_string())
+        };
+
+        assert_eq!(top_level_ancestor(2010, &processes, no_start_time, image_path).unwrap().pid, 2000);
+        assert_eq!(top_level_ancestor(2020, &processes, no_start_time, image_path).unwrap().pid, 2020);
+        assert_eq!(top_level_ancestor(2030, &processes, no_start_time, image_path).unwrap().pid, 2020);
+        assert_eq!(top_level_ancestor(2040, &processes, no_start_time, image_path).unwrap().pid, 2020);
+        assert_eq!(top_level_ancestor(2050, &processes, no_start_time, image_path).unwrap().pid, 2050);
+    }
+
+    #[test]
+    fn parent_loops_from_reused_pids_end_the_walk() {
+        let processes = table(&[(10, 20, "a.exe"), (20, 10, "b.exe")]);
+
+        assert!(top_level_ancestor(10, &processes, no_start_time, no_image_path).is_some());
+    }
+
+    #[test]
+    fn a_parent_that_started_after_the_child_is_someone_else() {
+        // the real parent of 30 exited and its pid went to an unrelated process
+        let processes = table(&[(20, 1, "app.exe"), (30, 20, "app.exe")]);
+        let start_time = |pid| Some(if pid == 20 { 500 } else { 100 });
+
+        assert_eq!(top_level_ancestor(30, &processes, start_time, no_image_path).unwrap().pid, 30);
+        assert_eq!(top_level_ancestor(30, &processes, |_| Some(100), no_image_path).unwrap().pid, 20);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn identifies_the_running_test_process() {
+        let identity = process_identity(std::process::id()).unwrap();
+
+        assert_eq!(identity.process.parent_pid, Some(std::os::unix::process::parent_id()));
+        let exe = std::env::current_exe().unwrap();
+        assert_eq!(identity.process.image_path.as_deref(), exe.to_str());
+    }
+}
@@ -0,0 +1,66 @@
+// The Linux process table, read from /proc. Each /proc/<pid>/stat has the name, the parent and the start time.
+use std::fs;
+use super::{ProcessInfo, ProcessTable};
+
+pub(super) fn snapshot() -> ProcessTable {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        eprintln!("[ProcessInfo] Failed to read /proc");
+        return ProcessTable::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+        .filter_map(|pid| {
+            // the process can exit between listing /proc and reading its stat
+            let stat = read_stat(pid)?;
+            Some((pid, ProcessInfo { pid, parent_pid: (stat.parent_pid != 0).then_some(stat.parent_pid), exe_name: stat.name, image_path: None }))
+        })
+        .collect()
+}
+
+// only readable for the user's own processes
+pub(super) fn image_path(pid: u32) -> Option<String> {
+    fs::read_link(format!("/proc/{}/exe", pid)).ok()?.to_str().map(str::to_string)
+}
+
+// clock ticks since boot, a pid that was reused has a later one
+pub(super) fn start_time(pid: u32) -> Option<u64> {
+    Some(read_stat(pid)?.start_time)
+}
+
+#[derive(Debug, PartialEq)]
+struct Stat {
+    name: String,
+    parent_pid: u32,
+    start_time: u64,
+}
+
+fn read_stat(pid: u32) -> Option<Stat> {
+    parse_stat(&fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+// "1234 (name) S 1200 ...": the name can hold spaces and parentheses itself, so the fields start after the last ')'
+// the parent is the 4th field and the start time the 22nd, counting the pid and the name
+fn parse_stat(stat: &str) -> Option<Stat> {
+    let (head, fields) = stat.rsplit_once(')')?;
+    let (_, name) = head.split_once('(')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    Some(Stat {
+        name: name.to_string(),
+        parent_pid: fields.get(1)?.parse().ok()?,
+        start_time: fields.get(19)?.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_name_parent_and_start_time_from_stat() {
+        let stat = "1234 (Web Content (x)) S 1200 1234 1234 0 -1 4194560 5000 0 0 0 10 5 0 0 20 0 30 0 987654 123 456";
+
+        assert_eq!(parse_stat(stat), Some(Stat { name: "Web Content (x)".to_string(), parent_pid: 1200, start_time: 987654 }));
+        assert_eq!(parse_stat("1234 (firefox) S 1200 1234"), None);
+        assert_eq!(parse_stat("garbage"), None);
+    }
+}
@@ -0,0 +1,64 @@
+// The Windows process table, from a Toolhelp snapshot. The image paths come from opening each process.
+use windows::{
+    core::PWSTR,
+    Win32::{Foundation::{CloseHandle, FILETIME}, System::{Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS
+    }, Threading::{
+        GetProcessTimes, OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION
+    }}},
+};
+use super::{ProcessInfo, ProcessTable};
+
+// every running process with its parent, an empty table when the snapshot can't be taken
+// a snapshot copies the whole process list, the cache only takes one when a lookup misses
+pub(super) fn snapshot() -> ProcessTable {
+    let mut processes = ProcessTable::new();
+    unsafe {
+        let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else {
+            eprintln!("[ProcessInfo] Failed to take a process snapshot");
+            return processes;
+        };
+        let mut entry = PROCESSENTRY32W { dwSize: std::mem::size_of::<PROCESSENTRY32W>() as u32, ..Default::default() };
+        let mut next = Process32FirstW(snapshot, &mut entry);
+        while next.is_ok() {
+            let name: Vec<u16> = entry.szExeFile.iter().take_while(|&&c| c != 0).copied().collect();
+            let pid = entry.th32ProcessID;
+            processes.insert(pid, ProcessInfo {
+                pid,
+                // the idle process and System have no parent, the others keep the pid even after the parent exited
+                parent_pid: (pid != 0 && entry.th32ParentProcessID != 0).then_some(entry.th32ParentProcessID),
+                exe_name: String::from_utf16_lossy(&name),
+                image_path: None,
+            });
+            next = Process32NextW(snapshot, &mut entry);
+        }
+        let _ = CloseHandle(snapshot);
+    }
+    processes
+}
+
+// the full path of the process's executable ("C:\Program Files\...\chrome.exe"), the snapshot only has the file name
+// limited information is enough for it, so it also works for most elevated processes
+pub(super) fn image_path(pid: u32) -> Option<String> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut buffer = [0u16; 1024];
+        let mut size = buffer.len() as u32;
+        let result = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(buffer.as_mut_ptr()), &mut size);
+        let _ = CloseHandle(process);
+        result.ok()?;
+        Some(String::from_utf16_lossy(&buffer[..size as usize]))
+    }
+}
+
+// when the process was created, in 100ns ticks since 1601. Two processes with the same pid never share it
+pub(super) fn start_time(pid: u32) -> Option<u64> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let (mut created, mut exited, mut kernel, mut user) = (FILETIME::default(), FILETIME::default(), FILETIME::default(), FILETIME::default());
+        let result = GetProcessTimes(process, &mut created, &mut exited, &mut kernel, &mut user);
+        let _ = CloseHandle(process);
+        result.ok()?;
+        Some(((created.dwHighDateTime as u64) << 32) | created.dwLowDateTime as u64)
+    }
+}